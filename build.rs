@@ -86,7 +86,7 @@ fn get_output_path() -> PathBuf {
     let build_type = std::env::var("PROFILE").unwrap();
     let path = Path::new(&manifest_dir_string).join("target").join(build_type);
 
-    PathBuf::from(path)
+    path
 }
 
 fn get_shader_output_path(build_path: PathBuf) -> PathBuf {
@@ -97,7 +97,8 @@ fn get_shader_output_path(build_path: PathBuf) -> PathBuf {
     shader_output
 }
 
-fn compile_source(stage: naga::ShaderStage, source: &str) {
+// `stage' is only needed for GLSL, WGSL declares it on its entry point.
+fn compile_source(stage: Option<naga::ShaderStage>, source: &str) {
     let validation_caps = naga::valid::Capabilities::CLIP_DISTANCE | naga::valid::Capabilities::CULL_DISTANCE;
     let shader_output_path = get_shader_output_path(get_output_path());
     let mut parser = naga::front::glsl::Parser::default();
@@ -129,13 +130,20 @@ fn compile_source(stage: naga::ShaderStage, source: &str) {
 
     let input = preprocess_shader!(std::fs::read_to_string(source).unwrap());
 
-    let module = parser.parse(&naga::front::glsl::Options {
-        stage,
-        defines: Default::default(),
-    }, &input).unwrap_or_else(|errors| {
-        emit_glsl_parser_error(errors, "terrain.comp", &input);
-        std::process::exit(1);
-    });
+    let module = match stage {
+        Some(stage) => parser.parse(&naga::front::glsl::Options {
+            stage,
+            defines: Default::default(),
+        }, &input).unwrap_or_else(|errors| {
+            emit_glsl_parser_error(errors, "terrain.comp", &input);
+            std::process::exit(1);
+        }),
+        // naga's GLSL frontend doesn't do atomics yet, compute shaders needing them are WGSL.
+        None => naga::front::wgsl::parse_str(&input).unwrap_or_else(|error| {
+            error.emit_to_stderr_with_path(&input, source);
+            std::process::exit(1);
+        }),
+    };
 
     let info = match naga::valid::Validator::new(naga::valid::ValidationFlags::empty(), validation_caps).validate(&module) {
         Ok(info) => info,
@@ -166,12 +174,14 @@ fn compile_source(stage: naga::ShaderStage, source: &str) {
         &pipeline_options_owned
     };
 
-    let mut options = naga::back::spv::Options::default();
+    let mut options = naga::back::spv::Options {
+        bounds_check_policies: naga::proc::BoundsCheckPolicies::default(),
+        ..Default::default()
+    };
 
-    options.bounds_check_policies = naga::proc::BoundsCheckPolicies::default();
     options.flags.set(
         naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE,
-        !false,
+        true,
     );
 
     let output = naga::back::spv::write_vec(&module, &info, &options, Some(pipeline_options)).unwrap();
@@ -187,8 +197,10 @@ fn compile_source(stage: naga::ShaderStage, source: &str) {
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=./Cargo.lock");
+    // Picks up newly added shaders, not just edits to the ones seen last time.
+    println!("cargo:rerun-if-changed=./res/shaders");
 
-    let sane_shader_extensions = vec!["vert", "frag"];
+    let sane_shader_extensions = ["vert", "frag", "wgsl"];
     for element in std::path::Path::new(r"./res/shaders/").read_dir().unwrap() {
         let path = &element.unwrap().path();
         if let Some(extension) = path.extension() {
@@ -198,8 +210,9 @@ fn main() {
                 println!("cargo:rerun-if-changed={}", path.display());
 
                 let shader_stage = match extension_str {
-                    "vert" => naga::ShaderStage::Vertex,
-                    "frag" => naga::ShaderStage::Fragment,
+                    "vert" => Some(naga::ShaderStage::Vertex),
+                    "frag" => Some(naga::ShaderStage::Fragment),
+                    "wgsl" => None,
                     _ => unreachable!(),
                 };
 
@@ -0,0 +1,9 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+void main() {
+    fragment_color = sample_input(tex_coords);
+
+    return;
+}
@@ -0,0 +1,10 @@
+#version 460
+
+%include res/shaders/h_post.frag
+%include res/shaders/h_bloom_blur.frag
+
+void main() {
+    fragment_color = blur(vec2(1.0, 0.0));
+
+    return;
+}
@@ -0,0 +1,13 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+// Adds the blurred highlights back onto the image. params: threshold, intensity.
+void main() {
+    vec4 color = sample_input(tex_coords);
+    vec3 bloom = texture(sampler2D(secondary_texture, input_sampler), tex_coords).rgb;
+
+    fragment_color = vec4(color.rgb + bloom * params.y, color.a);
+
+    return;
+}
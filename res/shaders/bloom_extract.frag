@@ -0,0 +1,14 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+// Keeps whatever is brighter than the threshold. params: threshold.
+void main() {
+    vec3 color = sample_input(tex_coords).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    float contribution = max(brightness - params.x, 0.0) / max(brightness, 0.0001);
+
+    fragment_color = vec4(color * contribution, 1.0);
+
+    return;
+}
@@ -0,0 +1,67 @@
+// Copies the instances of one mesh that are inside the view frustum into its part of
+// `visible_instances', counting them in the mesh's indirect draw arguments. One
+// invocation per instance, dispatched once per mesh.
+
+// Mirrors `InstanceRaw' in src/ecs/component/instance.rs.
+struct Instance {
+    model: mat4x4<f32>,
+    normal: mat3x4<f32>,
+    tint: vec4<f32>,
+    custom: vec4<f32>,
+};
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct Frustum {
+    planes: array<vec4<f32>, 6>,
+};
+
+// Object space bounds of the mesh, which of `draws' to fill in and where its instances
+// start in `visible_instances'.
+struct CullDraw {
+    center: vec4<f32>,
+    half_extents: vec4<f32>,
+    instance_count: u32,
+    draw_index: u32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0) var<uniform> frustum: Frustum;
+@group(0) @binding(1) var<storage, read_write> draws: array<DrawIndexedIndirect>;
+@group(0) @binding(2) var<storage, read_write> visible_instances: array<Instance>;
+
+@group(1) @binding(0) var<uniform> draw: CullDraw;
+@group(1) @binding(1) var<storage, read> instances: array<Instance>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= draw.instance_count) {
+        return;
+    }
+
+    let model = instances[index].model;
+    let center = (model * vec4<f32>(draw.center.xyz, 1.0)).xyz;
+    let half_extents = vec3<f32>(
+        dot(abs(vec3<f32>(model[0].x, model[1].x, model[2].x)), draw.half_extents.xyz),
+        dot(abs(vec3<f32>(model[0].y, model[1].y, model[2].y)), draw.half_extents.xyz),
+        dot(abs(vec3<f32>(model[0].z, model[1].z, model[2].z)), draw.half_extents.xyz),
+    );
+
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = frustum.planes[i];
+        let radius = dot(abs(plane.xyz), half_extents);
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&draws[draw.draw_index].instance_count, 1u);
+    visible_instances[draw.first_instance + slot] = instances[index];
+}
@@ -0,0 +1,16 @@
+#version 460
+
+layout(location = 0) smooth out vec2 tex_coords;
+
+// One triangle covering the whole screen, no vertex buffers needed.
+void main() {
+    vec2 uv = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+    tex_coords = vec2(uv.x, 1.0 - uv.y);
+
+    gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+    // Only y needs flipping, remapping z like the scene shaders would push it out of 0..1
+    // and clip the whole triangle.
+    gl_Position.y = -gl_Position.y;
+
+    return;
+}
@@ -0,0 +1,39 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+// Blurs along edges detected from luma contrast, after Timothy Lottes' FXAA.
+// params: span max (in texels), reduce multiplier, reduce minimum.
+void main() {
+    vec2 texel = input_texel_size();
+    vec3 luma_weights = vec3(0.299, 0.587, 0.114);
+
+    float luma_nw = dot(sample_input(tex_coords + vec2(-1.0, -1.0) * texel).rgb, luma_weights);
+    float luma_ne = dot(sample_input(tex_coords + vec2(1.0, -1.0) * texel).rgb, luma_weights);
+    float luma_sw = dot(sample_input(tex_coords + vec2(-1.0, 1.0) * texel).rgb, luma_weights);
+    float luma_se = dot(sample_input(tex_coords + vec2(1.0, 1.0) * texel).rgb, luma_weights);
+    vec4 center = sample_input(tex_coords);
+    float luma_m = dot(center.rgb, luma_weights);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 direction = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * params.y, params.z);
+    float inverse_direction_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * inverse_direction_min, vec2(-params.x), vec2(params.x)) * texel;
+
+    vec3 color_a = 0.5 * (
+        sample_input(tex_coords + direction * (1.0 / 3.0 - 0.5)).rgb +
+        sample_input(tex_coords + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 color_b = color_a * 0.5 + 0.25 * (
+        sample_input(tex_coords + direction * -0.5).rgb +
+        sample_input(tex_coords + direction * 0.5).rgb
+    );
+    float luma_b = dot(color_b, luma_weights);
+
+    fragment_color = vec4(luma_b < luma_min || luma_b > luma_max ? color_a : color_b, center.a);
+
+    return;
+}
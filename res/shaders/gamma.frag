@@ -0,0 +1,12 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+// params: gamma.
+void main() {
+    vec4 color = sample_input(tex_coords);
+
+    fragment_color = vec4(pow(max(color.rgb, vec3(0.0)), vec3(1.0 / params.x)), color.a);
+
+    return;
+}
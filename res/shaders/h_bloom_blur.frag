@@ -0,0 +1,16 @@
+// Separable 9 tap gaussian along `direction'. params.z spreads the taps.
+vec4 blur(vec2 direction) {
+    vec2 step_size = direction * input_texel_size() * params.z;
+    vec4 color = sample_input(tex_coords) * 0.227027;
+
+    color += sample_input(tex_coords + step_size * 1.0) * 0.1945946;
+    color += sample_input(tex_coords - step_size * 1.0) * 0.1945946;
+    color += sample_input(tex_coords + step_size * 2.0) * 0.1216216;
+    color += sample_input(tex_coords - step_size * 2.0) * 0.1216216;
+    color += sample_input(tex_coords + step_size * 3.0) * 0.054054;
+    color += sample_input(tex_coords - step_size * 3.0) * 0.054054;
+    color += sample_input(tex_coords + step_size * 4.0) * 0.016216;
+    color += sample_input(tex_coords - step_size * 4.0) * 0.016216;
+
+    return color;
+}
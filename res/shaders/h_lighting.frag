@@ -0,0 +1,41 @@
+#define MAX_LIGHTS 16u
+#define LIGHT_DIRECTIONAL 0.0
+#define LIGHT_POINT 1.0
+#define LIGHT_SPOT 2.0
+
+// Mirrors `LightRaw' in src/ecs/component/light.rs. `cone.z' is set on the light that
+// casts shadows.
+struct Light {
+    vec4 position;
+    vec4 direction;
+    vec4 color;
+    vec4 cone;
+};
+
+layout(set = 2, binding = 0) uniform LightData {
+    uvec4 light_count;
+    vec4 ambient;
+    Light lights[MAX_LIGHTS];
+};
+
+// Direction towards the light and how much of it reaches `world_position'.
+void light_contribution(Light light, vec3 world_position, out vec3 light_direction, out float attenuation) {
+    attenuation = 1.0;
+
+    if (light.position.w == LIGHT_DIRECTIONAL) {
+        light_direction = normalize(-light.direction.xyz);
+        return;
+    }
+
+    vec3 to_light = light.position.xyz - world_position;
+    float distance = length(to_light);
+    light_direction = to_light / max(distance, 0.0001);
+
+    float falloff = clamp(1.0 - distance / max(light.direction.w, 0.0001), 0.0, 1.0);
+    attenuation = falloff * falloff;
+
+    if (light.position.w == LIGHT_SPOT) {
+        float theta = dot(light_direction, normalize(-light.direction.xyz));
+        attenuation *= clamp((theta - light.cone.y) / max(light.cone.x - light.cone.y, 0.0001), 0.0, 1.0);
+    }
+}
@@ -0,0 +1,21 @@
+precision highp float;
+precision highp int;
+
+// Shared by every post-processing effect, see src/post_process.rs. `params' holds the
+// effect's settings, `secondary_texture' is whatever else it blends in (the input again
+// for most effects).
+layout(set = 0, binding = 0) uniform texture2D input_texture;
+layout(set = 0, binding = 1) uniform sampler input_sampler;
+layout(set = 0, binding = 2) uniform EffectData { vec4 params; };
+layout(set = 0, binding = 3) uniform texture2D secondary_texture;
+
+layout(location = 0) smooth in vec2 tex_coords;
+layout(location = 0) out vec4 fragment_color;
+
+vec4 sample_input(vec2 uv) {
+    return texture(sampler2D(input_texture, input_sampler), uv);
+}
+
+vec2 input_texel_size() {
+    return 1.0 / vec2(textureSize(sampler2D(input_texture, input_sampler), 0));
+}
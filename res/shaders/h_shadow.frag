@@ -0,0 +1,56 @@
+#define MAX_CASCADES 4
+
+// Mirrors `ShadowUniform' in src/shadow.rs.
+layout(set = 3, binding = 0) uniform ShadowData {
+    mat4x4 cascade_view_proj[MAX_CASCADES];
+    // View space depth at which each cascade ends.
+    vec4 cascade_splits;
+    // Cascade count (0 when nothing casts shadows), depth bias, PCF radius in texels and
+    // texel size.
+    vec4 shadow_params;
+};
+layout(set = 3, binding = 1) uniform texture2DArray shadow_map;
+layout(set = 3, binding = 2) uniform samplerShadow shadow_sampler;
+
+// 1.0 when `world_position' is fully lit by the shadow casting light, 0.0 when it's in
+// its shadow. `view_depth' picks the cascade.
+float shadow_factor(vec3 world_position, vec3 normal, vec3 light_direction, float view_depth) {
+    int cascade_count = int(shadow_params.x);
+    if (cascade_count == 0) {
+        return 1.0;
+    }
+
+    int cascade = cascade_count - 1;
+    for (int i = 0; i < cascade_count; i++) {
+        if (view_depth < cascade_splits[i]) {
+            cascade = i;
+            break;
+        }
+    }
+
+    vec4 light_clip = cascade_view_proj[cascade] * vec4(world_position, 1.0);
+    vec3 light_ndc = light_clip.xyz / light_clip.w;
+    vec2 uv = light_ndc.xy * vec2(0.5, -0.5) + 0.5;
+
+    // Past the last cascade (or outside the light's frustum) everything is lit.
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || light_ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    // Surfaces facing away from the light need more bias to avoid acne.
+    float bias = shadow_params.y * max(1.0 - dot(normal, light_direction), 0.1);
+    float depth = light_ndc.z - bias;
+    int radius = int(shadow_params.z);
+    float lit = 0.0;
+    float samples = 0.0;
+
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 offset = vec2(float(x), float(y)) * shadow_params.w;
+            lit += texture(sampler2DArrayShadow(shadow_map, shadow_sampler), vec4(uv + offset, float(cascade), depth));
+            samples += 1.0;
+        }
+    }
+
+    return lit / samples;
+}
@@ -4,6 +4,7 @@ precision highp int;
 struct Camera {
     vec4 view_pos;
     mat4x4 view_proj;
+    mat4x4 inv_view_proj;
 };
 
 struct VertexInput {
@@ -0,0 +1,51 @@
+#version 460
+
+%include res/shaders/h_vertex.vert
+%include res/shaders/h_lighting.frag
+%include res/shaders/h_shadow.frag
+
+#define SHININESS 32.0
+#define SPECULAR_STRENGTH 0.5
+
+layout(set = 0, binding = 0) uniform CameraData { Camera camera; };
+
+layout(set = 1, binding = 0) uniform texture2D base_color_texture;
+layout(set = 1, binding = 1) uniform sampler base_color_sampler;
+layout(set = 1, binding = 2) uniform MaterialData { vec4 tint; };
+
+layout(location = 0) smooth in vec3 world_position;
+layout(location = 1) smooth in vec3 world_normal;
+layout(location = 2) smooth in vec2 tex_coords;
+layout(location = 4) flat in vec4 instance_tint;
+layout(location = 0) out vec4 fragment_color;
+
+void main() {
+    vec4 base_color = texture(sampler2D(base_color_texture, base_color_sampler), tex_coords) * tint * instance_tint;
+    vec3 normal = normalize(world_normal);
+    vec3 view_direction = normalize(camera.view_pos.xyz - world_position);
+
+    vec3 diffuse = ambient.rgb;
+    vec3 specular = vec3(0.0);
+
+    for (uint i = 0u; i < min(light_count.x, MAX_LIGHTS); i++) {
+        Light light = lights[i];
+        vec3 light_direction;
+        float attenuation;
+        light_contribution(light, world_position, light_direction, attenuation);
+        if (light.cone.z > 0.0) {
+            attenuation *= shadow_factor(world_position, normal, light_direction, 1.0 / gl_FragCoord.w);
+        }
+
+        vec3 radiance = light.color.rgb * light.color.w * attenuation;
+        float lambert = max(dot(normal, light_direction), 0.0);
+        vec3 half_direction = normalize(light_direction + view_direction);
+        float highlight = lambert > 0.0 ? pow(max(dot(normal, half_direction), 0.0), SHININESS) : 0.0;
+
+        diffuse += radiance * lambert;
+        specular += radiance * highlight * SPECULAR_STRENGTH;
+    }
+
+    fragment_color = vec4(base_color.rgb * diffuse + specular, base_color.a);
+
+    return;
+}
@@ -0,0 +1,45 @@
+#version 460
+
+%include res/shaders/h_vertex.vert
+
+layout(binding = 0) uniform CameraData { Camera camera; };
+
+layout(location = 0) in vec3 vertex_position;
+layout(location = 1) in vec3 vertex_normal;
+layout(location = 2) in vec2 vertex_tex_coords;
+layout(location = 5) in vec4 model_matrix_5;
+layout(location = 6) in vec4 model_matrix_6;
+layout(location = 7) in vec4 model_matrix_7;
+layout(location = 8) in vec4 model_matrix_8;
+layout(location = 9) in vec3 normal_matrix_9;
+layout(location = 10) in vec3 normal_matrix_10;
+layout(location = 11) in vec3 normal_matrix_11;
+layout(location = 12) in vec4 instance_tint_12;
+layout(location = 13) in vec4 instance_custom_13;
+layout(location = 0) smooth out vec3 world_position;
+layout(location = 1) smooth out vec3 world_normal;
+layout(location = 2) smooth out vec2 tex_coords;
+// Zero here, meshes with tangents go through tangent_vertex.vert.
+layout(location = 3) smooth out vec4 world_tangent;
+layout(location = 4) flat out vec4 instance_tint;
+// For fragment shaders that make use of the instances' custom data.
+layout(location = 5) flat out vec4 instance_custom;
+
+void main() {
+    InstanceInput instance = InstanceInput(model_matrix_5, model_matrix_6, model_matrix_7, model_matrix_8);
+    mat4x4 model_matrix = mat4x4(instance.model_matrix_0_, instance.model_matrix_1_, instance.model_matrix_2_, instance.model_matrix_3_);
+    mat3x3 normal_matrix = mat3x3(normal_matrix_9, normal_matrix_10, normal_matrix_11);
+    vec4 world = model_matrix * vec4(vertex_position, 1.0);
+
+    world_position = world.xyz;
+    world_normal = normalize(normal_matrix * vertex_normal);
+    tex_coords = vertex_tex_coords;
+    instance_tint = instance_tint_12;
+    instance_custom = instance_custom_13;
+    world_tangent = vec4(0.0);
+
+    gl_Position = camera.view_proj * world;
+    gl_Position.yz = vec2(-gl_Position.y, gl_Position.z * 2.0 - gl_Position.w);
+
+    return;
+}
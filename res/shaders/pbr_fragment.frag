@@ -0,0 +1,126 @@
+#version 460
+
+%include res/shaders/h_vertex.vert
+%include res/shaders/h_lighting.frag
+%include res/shaders/h_shadow.frag
+
+#define PI 3.14159265359
+#define DIELECTRIC_REFLECTANCE 0.04
+
+layout(set = 0, binding = 0) uniform CameraData { Camera camera; };
+
+layout(set = 1, binding = 0) uniform texture2D base_color_texture;
+layout(set = 1, binding = 1) uniform texture2D metallic_roughness_texture;
+layout(set = 1, binding = 2) uniform texture2D normal_texture;
+layout(set = 1, binding = 3) uniform texture2D occlusion_texture;
+layout(set = 1, binding = 4) uniform texture2D emissive_texture;
+layout(set = 1, binding = 5) uniform sampler material_sampler;
+// Mirrors `PbrMaterialUniform' in src/material.rs.
+layout(set = 1, binding = 6) uniform MaterialData {
+    vec4 base_color_factor;
+    vec4 emissive_factor;
+    vec4 params;
+};
+
+layout(location = 0) smooth in vec3 world_position;
+layout(location = 1) smooth in vec3 world_normal;
+layout(location = 2) smooth in vec2 tex_coords;
+layout(location = 3) smooth in vec4 world_tangent;
+layout(location = 4) flat in vec4 instance_tint;
+layout(location = 0) out vec4 fragment_color;
+
+// Trowbridge-Reitz GGX normal distribution.
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+
+    return a2 / max(PI * denominator * denominator, 0.0001);
+}
+
+// Smith's method with Schlick-GGX for both the view and light directions.
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float view = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float light = n_dot_l / (n_dot_l * (1.0 - k) + k);
+
+    return view * light;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Meshes without tangents get a frame built from screen-space derivatives instead.
+vec3 perturbed_normal(vec3 normal, vec3 tangent_normal) {
+    mat3 tbn;
+
+    if (dot(world_tangent.xyz, world_tangent.xyz) > 0.0) {
+        vec3 tangent = normalize(world_tangent.xyz - normal * dot(normal, world_tangent.xyz));
+        vec3 bitangent = cross(normal, tangent) * (world_tangent.w < 0.0 ? -1.0 : 1.0);
+        tbn = mat3(tangent, bitangent, normal);
+    } else {
+        vec3 dp1 = dFdx(world_position);
+        vec3 dp2 = dFdy(world_position);
+        vec2 duv1 = dFdx(tex_coords);
+        vec2 duv2 = dFdy(tex_coords);
+
+        vec3 dp2_perp = cross(dp2, normal);
+        vec3 dp1_perp = cross(normal, dp1);
+        vec3 tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+        vec3 bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+        float scale = inversesqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 0.0000001));
+        tbn = mat3(tangent * scale, bitangent * scale, normal);
+    }
+
+    return normalize(tbn * tangent_normal);
+}
+
+void main() {
+    vec4 base_color = texture(sampler2D(base_color_texture, material_sampler), tex_coords) * base_color_factor * instance_tint;
+    vec4 metallic_roughness = texture(sampler2D(metallic_roughness_texture, material_sampler), tex_coords);
+    float metallic = clamp(metallic_roughness.b * params.x, 0.0, 1.0);
+    float roughness = clamp(metallic_roughness.g * params.y, 0.04, 1.0);
+
+    vec3 tangent_normal = texture(sampler2D(normal_texture, material_sampler), tex_coords).xyz * 2.0 - 1.0;
+    tangent_normal.xy *= params.z;
+    vec3 normal = perturbed_normal(normalize(world_normal), normalize(tangent_normal));
+    vec3 view_direction = normalize(camera.view_pos.xyz - world_position);
+    float n_dot_v = max(dot(normal, view_direction), 0.0001);
+
+    vec3 f0 = mix(vec3(DIELECTRIC_REFLECTANCE), base_color.rgb, metallic);
+    vec3 radiance_out = vec3(0.0);
+
+    for (uint i = 0u; i < min(light_count.x, MAX_LIGHTS); i++) {
+        Light light = lights[i];
+        vec3 light_direction;
+        float attenuation;
+        light_contribution(light, world_position, light_direction, attenuation);
+        if (light.cone.z > 0.0) {
+            attenuation *= shadow_factor(world_position, normal, light_direction, 1.0 / gl_FragCoord.w);
+        }
+
+        float n_dot_l = max(dot(normal, light_direction), 0.0);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+
+        vec3 half_direction = normalize(light_direction + view_direction);
+        float n_dot_h = max(dot(normal, half_direction), 0.0);
+        vec3 fresnel = fresnel_schlick(max(dot(half_direction, view_direction), 0.0), f0);
+
+        vec3 specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+            / (4.0 * n_dot_v * n_dot_l + 0.0001);
+        vec3 diffuse = (vec3(1.0) - fresnel) * (1.0 - metallic) * base_color.rgb / PI;
+
+        radiance_out += (diffuse + specular) * light.color.rgb * light.color.w * attenuation * n_dot_l;
+    }
+
+    float occlusion = texture(sampler2D(occlusion_texture, material_sampler), tex_coords).r;
+    occlusion = mix(1.0, occlusion, params.w);
+    vec3 emissive = texture(sampler2D(emissive_texture, material_sampler), tex_coords).rgb * emissive_factor.rgb;
+
+    fragment_color = vec4(ambient.rgb * base_color.rgb * occlusion + radiance_out + emissive, base_color.a);
+
+    return;
+}
@@ -0,0 +1,23 @@
+#version 460
+
+%include res/shaders/h_vertex.vert
+
+layout(set = 0, binding = 0) uniform ShadowCascade { mat4x4 light_view_proj; };
+
+layout(location = 0) in vec3 vertex_position;
+layout(location = 5) in vec4 model_matrix_5;
+layout(location = 6) in vec4 model_matrix_6;
+layout(location = 7) in vec4 model_matrix_7;
+layout(location = 8) in vec4 model_matrix_8;
+
+void main() {
+    InstanceInput instance = InstanceInput(model_matrix_5, model_matrix_6, model_matrix_7, model_matrix_8);
+    mat4x4 model_matrix = mat4x4(instance.model_matrix_0_, instance.model_matrix_1_, instance.model_matrix_2_, instance.model_matrix_3_);
+
+    gl_Position = light_view_proj * model_matrix * vec4(vertex_position, 1.0);
+    // Unlike the other passes, depth is kept as is (0 to 1), since `shadow_factor()'
+    // compares it against the light space depth of the fragment.
+    gl_Position.y = -gl_Position.y;
+
+    return;
+}
@@ -0,0 +1,17 @@
+#version 460
+
+precision highp float;
+precision highp int;
+
+layout(set = 1, binding = 0) uniform textureCube environment_texture;
+layout(set = 1, binding = 1) uniform sampler environment_sampler;
+
+layout(location = 0) smooth in vec3 view_direction;
+layout(location = 0) out vec4 fragment_color;
+
+void main() {
+    vec3 color = texture(samplerCube(environment_texture, environment_sampler), normalize(view_direction)).rgb;
+    fragment_color = vec4(color, 1.0);
+
+    return;
+}
@@ -0,0 +1,21 @@
+#version 460
+
+%include res/shaders/h_vertex.vert
+
+layout(set = 0, binding = 0) uniform CameraData { Camera camera; };
+
+layout(location = 0) smooth out vec3 view_direction;
+
+// A fullscreen triangle on the far plane, so it only shows where nothing else was drawn.
+void main() {
+    vec2 uv = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+    vec4 clip_position = vec4(uv * 2.0 - 1.0, 1.0, 1.0);
+
+    vec4 world_position = camera.inv_view_proj * clip_position;
+    view_direction = world_position.xyz / world_position.w - camera.view_pos.xyz;
+
+    gl_Position = clip_position;
+    gl_Position.yz = vec2(-gl_Position.y, gl_Position.z * 2.0 - gl_Position.w);
+
+    return;
+}
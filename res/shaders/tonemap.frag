@@ -0,0 +1,24 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+#define OPERATOR_ACES 0.0
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 reinhard(vec3 x) {
+    return x / (1.0 + x);
+}
+
+// params: operator, exposure.
+void main() {
+    vec4 color = sample_input(tex_coords);
+    vec3 exposed = color.rgb * params.y;
+
+    fragment_color = vec4(params.x == OPERATOR_ACES ? aces(exposed) : reinhard(exposed), color.a);
+
+    return;
+}
@@ -10,6 +10,7 @@ layout(location = 5) in vec4 model_matrix_5;
 layout(location = 6) in vec4 model_matrix_6;
 layout(location = 7) in vec4 model_matrix_7;
 layout(location = 8) in vec4 model_matrix_8;
+layout(location = 12) in vec4 instance_tint;
 layout(location = 0) smooth out vec3 vertex_color;
 
 void main() {
@@ -18,7 +19,7 @@ void main() {
     InstanceInput instance = InstanceInput(model_matrix_5, model_matrix_6, model_matrix_7, model_matrix_8);
     mat4x4 model_matrix = mat4x4(instance.model_matrix_0_, instance.model_matrix_1_, instance.model_matrix_2_, instance.model_matrix_3_);
 
-    vertex_out.color = model.color;
+    vertex_out.color = model.color * instance_tint.rgb;
     vertex_out.clip_position = ((camera.view_proj * model_matrix) * vec4(model.position, 1.0));
     vertex_color = vertex_out.color;
 
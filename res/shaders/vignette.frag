@@ -0,0 +1,14 @@
+#version 460
+
+%include res/shaders/h_post.frag
+
+// params: intensity, radius, softness.
+void main() {
+    vec4 color = sample_input(tex_coords);
+    float distance_from_center = distance(tex_coords, vec2(0.5));
+    float falloff = smoothstep(params.y, params.y - params.z, distance_from_center);
+
+    fragment_color = vec4(color.rgb * mix(1.0, falloff, params.x), color.a);
+
+    return;
+}
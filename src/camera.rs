@@ -21,6 +21,14 @@ const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 pub struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    // Takes clip space back to world space, e.g. for the skybox' view rays.
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CameraUniform {
@@ -28,12 +36,15 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
         self.view_position = camera.position.to_homogeneous().into();
-        self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into()
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj.invert().unwrap_or_else(Matrix4::identity).into();
     }
 }
 
@@ -41,7 +52,7 @@ impl CameraUniform {
 pub struct Camera {
     pub position: Point3<f32>,
     pub yaw: Rad<f32>,
-    pitch: Rad<f32>,
+    pub pitch: Rad<f32>,
 }
 
 impl Camera {
@@ -91,7 +102,20 @@ impl Projection {
     }
 
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        self.calc_matrix_with_range(self.znear, self.zfar)
+    }
+
+    // The same projection, clipped to a different depth range (e.g. one shadow cascade).
+    pub fn calc_matrix_with_range(&self, znear: f32, zfar: f32) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, znear, zfar)
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
     }
 }
 
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3, Vector4};
+
+use crate::ecs::object::Entity;
+use crate::ecs::scene::Scene;
+use crate::ecs::component::mesh::MeshComponent;
+use crate::ecs::component::instance::InstanceComponent;
+
+// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    // Empty point sets give an empty box at the origin.
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        let mut points = points.iter().map(|point| Point3::from(*point));
+
+        let first = match points.next() {
+            Some(point) => point,
+            None => return Self { min: Point3::origin(), max: Point3::origin() },
+        };
+
+        points.fold(Self { min: first, max: first }, |aabb, point| Self {
+            min: Point3::new(aabb.min.x.min(point.x), aabb.min.y.min(point.y), aabb.min.z.min(point.z)),
+            max: Point3::new(aabb.max.x.max(point.x), aabb.max.y.max(point.y), aabb.max.z.max(point.z)),
+        })
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    // The box around the transformed box, which may be larger than the transformed
+    // contents' own bounds.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = matrix.transform_point(self.center());
+        let half_extents = self.half_extents();
+
+        let extent = |row: usize| {
+            let row = matrix.row(row);
+            row.x.abs() * half_extents.x + row.y.abs() * half_extents.y + row.z.abs() * half_extents.z
+        };
+        let extents = Vector3::new(extent(0), extent(1), extent(2));
+
+        Self { min: center - extents, max: center + extents }
+    }
+}
+
+// The six planes (xyz normal pointing inwards, w distance) of a view-projection matrix's
+// frustum, for clip space depth in 0..1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn from_matrix(view_proj: &Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3));
+
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 { plane / length } else { plane }
+        });
+
+        Self { planes }
+    }
+
+    // Conservative: boxes near the frustum's corners may pass without being visible.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center().to_vec();
+        let half_extents = aabb.half_extents();
+
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let radius = normal.x.abs() * half_extents.x + normal.y.abs() * half_extents.y + normal.z.abs() * half_extents.z;
+
+            normal.dot(center) + plane.w >= -radius
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub meshes: u32,
+    pub culled_meshes: u32,
+    pub instances: u32,
+    pub culled_instances: u32,
+}
+
+// Meshes of an entity are told apart by their position among its meshes.
+pub type MeshKey = (Entity, usize);
+
+// Which instances of which meshes are inside the frustum this frame.
+#[derive(Default)]
+pub struct Visibility {
+    // Runs of visible instances, per mesh with instances.
+    instances: HashMap<MeshKey, Vec<Range<u32>>>,
+    pub stats: CullingStats,
+}
+
+impl Visibility {
+    // Tests every instance's world space bounds against `frustum', skipping the instances of
+    // meshes whose instances are all outside it in one go.
+    pub fn compute(scene: &Scene, frustum: &Frustum) -> Self {
+        let mut visibility = Self::default();
+
+        for (key, mesh_component, instance_component) in scene_meshes(scene) {
+            visibility.add(key, &instance_bounds(mesh_component, instance_component), frustum);
+        }
+
+        visibility
+    }
+
+    // Culls one mesh, given the world space bounds of each of its instances.
+    fn add(&mut self, key: MeshKey, bounds: &[Aabb], frustum: &Frustum) {
+        let count = bounds.len() as u32;
+        self.stats.meshes += 1;
+        self.stats.instances += count;
+
+        let mesh_visible = bounds.iter()
+            .copied()
+            .reduce(|a, b| a.union(&b))
+            .is_some_and(|mesh_bounds| frustum.intersects_aabb(&mesh_bounds));
+
+        let mut ranges: Vec<Range<u32>> = vec![];
+        if mesh_visible {
+            for (index, instance_bounds) in bounds.iter().enumerate() {
+                if !frustum.intersects_aabb(instance_bounds) {
+                    continue;
+                }
+
+                let index = index as u32;
+                match ranges.last_mut() {
+                    Some(range) if range.end == index => range.end += 1,
+                    _ => ranges.push(index..index + 1),
+                }
+            }
+        } else {
+            self.stats.culled_meshes += 1;
+        }
+
+        let visible = ranges.iter().map(|range| range.len() as u32).sum::<u32>();
+        self.stats.culled_instances += count - visible;
+        self.instances.insert(key, ranges);
+    }
+
+    // The runs of the mesh's instances to draw. Meshes that weren't around when the
+    // visibility was computed are drawn whole.
+    pub fn instance_ranges(&self, key: MeshKey, instance_count: u32) -> Vec<Range<u32>> {
+        match self.instances.get(&key) {
+            Some(ranges) => ranges.clone(),
+            None if instance_count > 0 => std::iter::once(0..instance_count).collect(),
+            None => vec![],
+        }
+    }
+}
+
+// `Scene::query2()', with each mesh keyed by its entity and its position among the
+// entity's meshes.
+pub fn scene_meshes(scene: &Scene) -> Vec<(MeshKey, &MeshComponent, &InstanceComponent)> {
+    let mut counts: HashMap<Entity, usize> = HashMap::new();
+
+    scene.query2::<MeshComponent, InstanceComponent>()
+        .map(|(entity, mesh_component, instance_component)| {
+            let count = counts.entry(entity).or_default();
+            let key = (entity, *count);
+            *count += 1;
+
+            (key, mesh_component, instance_component)
+        })
+        .collect()
+}
+
+fn instance_bounds(mesh_component: &MeshComponent, instance_component: &InstanceComponent) -> Vec<Aabb> {
+    instance_component.instances().iter()
+        .map(|instance| mesh_component.bounds.transform(&(instance_component.world * instance.to_matrix())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{perspective, Deg, Quaternion};
+    use crate::camera::OPENGL_TO_WGPU_MATRIX;
+
+    const EPSILON: f32 = 1e-5;
+
+    // Looking down -z from the origin, 90 degrees wide and high, depth from 0.1 to 100.
+    fn frustum() -> Frustum {
+        let view = Matrix4::look_to_rh(Point3::origin(), -Vector3::unit_z(), Vector3::unit_y());
+        let projection = OPENGL_TO_WGPU_MATRIX * perspective(Deg(90.0), 1.0, 0.1, 100.0);
+
+        Frustum::from_matrix(&(projection * view))
+    }
+
+    fn cube(center: [f32; 3], half_size: f32) -> Aabb {
+        let center = Point3::from(center);
+        let half_extents = Vector3::new(half_size, half_size, half_size);
+
+        Aabb { min: center - half_extents, max: center + half_extents }
+    }
+
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).magnitude() < EPSILON, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn frustum_planes_are_normalized() {
+        for plane in frustum().planes {
+            assert!((plane.truncate().magnitude() - 1.0).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn frustum_keeps_boxes_in_view() {
+        let frustum = frustum();
+
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -10.0], 1.0)));
+        // Straddling the left plane and the far plane.
+        assert!(frustum.intersects_aabb(&cube([-10.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -100.0], 1.0)));
+    }
+
+    #[test]
+    fn frustum_rejects_boxes_out_of_view() {
+        let frustum = frustum();
+
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, 10.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -0.05], 0.01)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -110.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([-20.0, 0.0, -10.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 20.0, -10.0], 1.0)));
+    }
+
+    #[test]
+    fn transform_moves_and_scales_boxes() {
+        let aabb = Aabb::from_points(&[[-1.0, 0.0, 0.0], [1.0, 2.0, 0.5]]);
+        let matrix = Matrix4::from_translation(Vector3::new(10.0, 0.0, -5.0)) * Matrix4::from_nonuniform_scale(2.0, 1.0, 4.0);
+        let transformed = aabb.transform(&matrix);
+
+        assert_close(transformed.min, Point3::new(8.0, 0.0, -5.0));
+        assert_close(transformed.max, Point3::new(12.0, 2.0, -3.0));
+    }
+
+    #[test]
+    fn transform_encloses_rotated_boxes() {
+        let aabb = cube([0.0; 3], 1.0);
+        let rotation = Matrix4::from(Quaternion::from_angle_y(Deg(45.0)));
+        let transformed = aabb.transform(&rotation);
+
+        let diagonal = 2.0f32.sqrt();
+        assert_close(transformed.min, Point3::new(-diagonal, -1.0, -diagonal));
+        assert_close(transformed.max, Point3::new(diagonal, 1.0, diagonal));
+    }
+
+    #[test]
+    fn empty_point_sets_give_an_empty_box() {
+        let aabb = Aabb::from_points(&[]);
+
+        assert_eq!(aabb.min, Point3::origin());
+        assert_eq!(aabb.max, Point3::origin());
+    }
+
+    #[test]
+    fn meshes_sharing_an_entity_are_culled_apart() {
+        let entity = Scene::new().spawn();
+        let frustum = frustum();
+
+        let mut visibility = Visibility::default();
+        visibility.add((entity, 0), &[cube([0.0, 0.0, -5.0], 1.0), cube([50.0, 0.0, -5.0], 1.0)], &frustum);
+        visibility.add((entity, 1), &[cube([0.0, 0.0, 5.0], 1.0), cube([0.0, 0.0, 10.0], 1.0)], &frustum);
+
+        assert_eq!(visibility.instance_ranges((entity, 0), 2), vec![0..1]);
+        assert!(visibility.instance_ranges((entity, 1), 2).is_empty());
+        // Meshes that weren't culled are drawn whole.
+        assert_eq!(visibility.instance_ranges((entity, 2), 2), vec![0..2]);
+
+        assert_eq!(visibility.stats.meshes, 2);
+        assert_eq!(visibility.stats.culled_meshes, 1);
+        assert_eq!(visibility.stats.culled_instances, 3);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+// A generational index into an `Arena<T>'. Removing a value bumps its slot's
+// generation, so stale handles stop resolving instead of pointing at whatever
+// reused the slot.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    // A handle that never resolves to anything.
+    pub const fn null() -> Self {
+        Self {
+            index: u32::MAX,
+            generation: u32::MAX,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Self::null()
+    }
+}
+
+// Derives would put bounds on `T', which handles don't need.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+impl<T> Default for Handle<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![],
+            free: vec![],
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        self.len += 1;
+
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+
+            return Handle { index, generation: slot.generation, _marker: PhantomData };
+        }
+
+        let index = self.slots.len() as u32;
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+
+        Handle { index, generation: 0, _marker: PhantomData }
+    }
+
+    pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index())?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+
+        let value = slot.value.take()?;
+        // Once a slot has been through every generation it's retired for good, otherwise
+        // old handles would start resolving again.
+        slot.generation = slot.generation.wrapping_add(1);
+        if slot.generation != u32::MAX {
+            self.free.push(handle.index);
+        }
+        self.len -= 1;
+
+        Some(value)
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots.get(handle.index())
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots.get_mut(handle.index())
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value.as_ref().map(|value| {
+                (Handle { index: index as u32, generation: slot.generation, _marker: PhantomData }, value)
+            })
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            let generation = slot.generation;
+            slot.value.as_mut().map(|value| {
+                (Handle { index: index as u32, generation, _marker: PhantomData }, value)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_slots_are_reused_with_a_new_generation() {
+        let mut arena = Arena::new();
+        let first = arena.insert("first");
+        arena.insert("second");
+
+        assert_eq!(arena.remove(first), Some("first"));
+        let third = arena.insert("third");
+
+        assert_eq!(third.index(), first.index());
+        assert_ne!(third.generation(), first.generation());
+        assert_eq!(arena.len(), 2);
+    }
+
+    #[test]
+    fn stale_handles_stop_resolving() {
+        let mut arena = Arena::new();
+        let stale = arena.insert(1);
+        arena.remove(stale);
+        let fresh = arena.insert(2);
+
+        assert!(!arena.contains(stale));
+        assert_eq!(arena.get(stale), None);
+        assert_eq!(arena.get_mut(stale), None);
+        assert_eq!(arena.remove(stale), None);
+        assert_eq!(arena.get(fresh), Some(&2));
+    }
+
+    #[test]
+    fn null_handles_never_resolve() {
+        let mut arena = Arena::new();
+        arena.insert(1);
+
+        assert!(Handle::<i32>::null().is_null());
+        assert_eq!(arena.get(Handle::null()), None);
+        assert_eq!(arena.remove(Handle::null()), None);
+    }
+
+    #[test]
+    fn iter_skips_removed_values() {
+        let mut arena = Arena::new();
+        let a = arena.insert('a');
+        let b = arena.insert('b');
+        let c = arena.insert('c');
+        arena.remove(b);
+
+        assert_eq!(arena.iter().collect::<Vec<_>>(), vec![(a, &'a'), (c, &'c')]);
+    }
+}
@@ -1,38 +1,96 @@
 use std::any::Any;
 use cgmath::prelude::*;
-use wgpu::util::DeviceExt;
 
+use crate::ecs::scene::Scene;
+use crate::ecs::system::{System, SystemContext};
 use crate::ecs::component::Component;
+use crate::ecs::component::transform::world_matrices;
+use crate::ecs::component::instance_layout::InstanceLayout;
 
 const DEFAULT_INSTANCES_PER_ROW: u32 = 10;
 pub const SINGLE_INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(0.0, 0.0, 0.0);
 pub const FANCY_MULTI_INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(DEFAULT_INSTANCES_PER_ROW
                                                                         as f32 * 0.5, 0.0,DEFAULT_INSTANCES_PER_ROW as f32 * 0.5);
 
+// Instances of the meshes on the same object. Edits only touch `instances', the buffer is
+// brought up to date by `upload()' (which `InstanceUpload' calls every frame).
 pub struct InstanceComponent {
     pub num_instances_per_row: u32,
     pub instance_displacement: cgmath::Vector3<f32>,
     pub instance_buffer: wgpu::Buffer,
-    pub instances: Vec<Instance>,
+    instances: Vec<Instance>,
+    // How many instances `instance_buffer' has room for.
+    capacity: usize,
+    // `instances' or `world' changed since the last upload.
+    dirty: bool,
+    // World matrix of the owning object that's baked into `instance_buffer'.
+    pub world: cgmath::Matrix4<f32>,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    // Multiplies the material's base colour.
+    pub tint: [f32; 4],
+    // Free for shaders to interpret, the built-in ones ignore it.
+    pub custom: [f32; 4],
 }
 
+// Mirrored by `Instance' in cull_instances.wgsl, which copies these around as a whole.
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
+    // Inverse transpose of the model matrix's 3x3 part, so normals survive non-uniform
+    // scale. Columns are padded to vec4s to keep the struct's layout the same in WGSL.
+    normal: [[f32; 4]; 3],
+    tint: [f32; 4],
+    custom: [f32; 4],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            position: cgmath::Vector3::zero(),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            tint: [1.0; 4],
+            custom: [0.0; 4],
+        }
+    }
 }
 
 impl Instance {
+    pub fn new(position: cgmath::Vector3<f32>, rotation: cgmath::Quaternion<f32>) -> Self {
+        Self { position, rotation, ..Default::default() }
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
+        self.to_raw_with_parent(&cgmath::Matrix4::identity())
+    }
+
+    // Places the instance relative to `parent', usually its object's world matrix.
+    pub fn to_raw_with_parent(&self, parent: &cgmath::Matrix4<f32>) -> InstanceRaw {
+        let model = parent * self.to_matrix();
+        let linear = cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        // Zero scale leaves nothing to see, any normal will do.
+        let normal = linear.invert().map_or(linear, |inverse| inverse.transpose());
+
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
+            model: model.into(),
+            normal: [normal.x.extend(0.0).into(), normal.y.extend(0.0).into(), normal.z.extend(0.0).into()],
+            tint: self.tint,
+            custom: self.custom,
         }
     }
+
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
 }
 
 impl InstanceRaw {
@@ -70,6 +128,34 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // The normal matrix, skipping each column's padding.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 24]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                // Tint.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 28]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Custom data.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 32]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -79,6 +165,10 @@ impl Component for InstanceComponent {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 impl InstanceComponent {
@@ -86,28 +176,140 @@ impl InstanceComponent {
         Self::new(device, 0, SINGLE_INSTANCE_DISPLACEMENT)
     }
 
+    // Exactly one instance, sitting at the object's origin.
+    pub fn single(device: &wgpu::Device) -> Self {
+        Self::new(device, 1, SINGLE_INSTANCE_DISPLACEMENT)
+    }
+
     pub fn new(device: &wgpu::Device, num_instances_per_row: u32, instance_displacement: cgmath::Vector3<f32>) -> Self {
-        let instances = Self::create_instances(num_instances_per_row, instance_displacement);
-        let instance_buffer = Self::create_instance_buffer(device, &instances);
+        let mut component = Self::from_instances(device, Self::create_instances(num_instances_per_row, instance_displacement));
+        component.num_instances_per_row = num_instances_per_row;
+        component.instance_displacement = instance_displacement;
+
+        component
+    }
+
+    // Exactly these instances, with room for no more.
+    pub fn from_instances(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        let world = cgmath::Matrix4::identity();
+        let capacity = instances.len().max(1);
+        let instance_buffer = Self::create_instance_buffer(device, capacity, true);
+
+        let instance_data = instances.iter().map(|i| i.to_raw_with_parent(&world)).collect::<Vec<_>>();
+        instance_buffer.slice(..).get_mapped_range_mut()[..std::mem::size_of_val(instance_data.as_slice())]
+            .copy_from_slice(bytemuck::cast_slice(&instance_data));
+        instance_buffer.unmap();
 
         Self {
-            num_instances_per_row,
-            instance_displacement,
+            num_instances_per_row: 0,
+            instance_displacement: SINGLE_INSTANCE_DISPLACEMENT,
             instance_buffer,
             instances,
+            capacity,
+            dirty: false,
+            world,
         }
     }
 
-    fn create_instance_buffer(device: &wgpu::Device, instances: &Vec<Instance>) -> wgpu::Buffer {
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+    pub fn from_layout(device: &wgpu::Device, layout: &dyn InstanceLayout) -> Self {
+        Self::from_instances(device, layout.instances())
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
 
-        device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        )
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn push(&mut self, instance: Instance) {
+        self.instances.push(instance);
+        self.dirty = true;
+    }
+
+    // Keeps the order of the remaining instances.
+    pub fn remove(&mut self, index: usize) -> Option<Instance> {
+        if index >= self.instances.len() {
+            return None;
+        }
+
+        self.dirty = true;
+        Some(self.instances.remove(index))
+    }
+
+    // Marks the instances dirty, whether or not the instance actually gets changed.
+    pub fn instance_mut(&mut self, index: usize) -> Option<&mut Instance> {
+        let instance = self.instances.get_mut(index)?;
+        self.dirty = true;
+
+        Some(instance)
+    }
+
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        self.instances = instances;
+        self.dirty = true;
+    }
+
+    // Replaces the instances with the ones `layout' comes up with.
+    pub fn set_layout(&mut self, layout: &dyn InstanceLayout) {
+        self.set_instances(layout.instances());
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = true;
+    }
+
+    // The instances get placed relative to `world' at the next upload.
+    pub fn set_world(&mut self, world: cgmath::Matrix4<f32>) {
+        if self.world != world {
+            self.world = world;
+            self.dirty = true;
+        }
+    }
+
+    // Writes the instances into `instance_buffer' if anything changed, reallocating it (with
+    // some headroom) only when they no longer fit.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+
+        if self.instances.len() > self.capacity {
+            self.capacity = self.instances.len() + self.instances.len() / 2;
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity, false);
+        }
+
+        if !self.instances.is_empty() {
+            let instance_data = self.instances.iter().map(|i| i.to_raw_with_parent(&self.world)).collect::<Vec<_>>();
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+        }
+
+        self.dirty = false;
+    }
+
+    // `mapped_at_creation' lets it be filled without a queue, the caller has to unmap it then.
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize, mapped_at_creation: bool) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            // Storage for `IndirectDraws' to cull from, or copy from without culling.
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation,
+        })
     }
 
     fn create_instances(num_instances_per_row: u32, instance_displacement: cgmath::Vector3<f32>) -> Vec<Instance> {
@@ -123,11 +325,24 @@ impl InstanceComponent {
                     cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
                 };
 
-                Instance {
-                    position, rotation,
-                }
+                Instance::new(position, rotation)
             })
         }).collect::<Vec<_>>()
     }
 }
 
+// Keeps instance buffers in line with their instances and their object's world matrix.
+// Objects without a transform draw their instances as-is.
+pub struct InstanceUpload;
+
+impl System for InstanceUpload {
+    fn run(&mut self, scene: &mut Scene, context: &SystemContext) {
+        let worlds = world_matrices(scene);
+
+        for (entity, instance_component) in scene.query_mut::<InstanceComponent>() {
+            let world = worlds.get(&entity).copied().unwrap_or_else(cgmath::Matrix4::identity);
+            instance_component.set_world(world);
+            instance_component.upload(context.device, context.queue);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use cgmath::prelude::*;
+use cgmath::{Deg, Quaternion, Rad, Vector3};
+
+use crate::culling::Aabb;
+use crate::ecs::component::instance::Instance;
+use crate::ecs::component::mesh::MeshComponent;
+
+// Somewhere instances come from, see `InstanceComponent::from_layout()'.
+pub trait InstanceLayout {
+    fn instances(&self) -> Vec<Instance>;
+}
+
+// The instances exactly as given.
+impl InstanceLayout for [Instance] {
+    fn instances(&self) -> Vec<Instance> {
+        self.to_vec()
+    }
+}
+
+impl InstanceLayout for Vec<Instance> {
+    fn instances(&self) -> Vec<Instance> {
+        self.clone()
+    }
+}
+
+// `counts' instances along each axis, `spacing' apart, every one of them a copy of
+// `template' moved into place.
+#[derive(Copy, Clone, Debug)]
+pub struct GridLayout {
+    pub counts: [u32; 3],
+    pub spacing: Vector3<f32>,
+    // Centres the grid on the origin instead of starting it there.
+    pub centered: bool,
+    pub template: Instance,
+}
+
+// Instances randomly placed inside `bounds'. The same seed always gives the same instances.
+#[derive(Copy, Clone, Debug)]
+pub struct VolumeScatter {
+    pub bounds: Aabb,
+    pub count: u32,
+    pub seed: u64,
+    pub variation: ScatterVariation,
+    pub template: Instance,
+}
+
+// Instances randomly placed on a mesh's triangles, with bigger triangles getting more of
+// them. The same seed always gives the same instances.
+#[derive(Clone, Debug)]
+pub struct SurfaceScatter {
+    triangles: Vec<[Vector3<f32>; 3]>,
+    pub count: u32,
+    pub seed: u64,
+    // Turns the instances' up (+y) to the triangle they're on.
+    pub align_to_normal: bool,
+    pub variation: ScatterVariation,
+    pub template: Instance,
+}
+
+// How much scattered instances differ from their template.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScatterVariation {
+    // Random turn around the instance's up axis.
+    pub random_yaw: bool,
+    // Uniform scale factor range, applied on top of the template's scale.
+    pub scale: (f32, f32),
+}
+
+impl Default for ScatterVariation {
+    fn default() -> Self {
+        Self { random_yaw: false, scale: (1.0, 1.0) }
+    }
+}
+
+impl GridLayout {
+    pub fn new(counts: [u32; 3], spacing: Vector3<f32>) -> Self {
+        Self { counts, spacing, centered: true, template: Instance::default() }
+    }
+}
+
+impl InstanceLayout for GridLayout {
+    fn instances(&self) -> Vec<Instance> {
+        let [nx, ny, nz] = self.counts;
+        let offset = if self.centered {
+            Vector3::new(nx.saturating_sub(1) as f32, ny.saturating_sub(1) as f32, nz.saturating_sub(1) as f32)
+                .mul_element_wise(self.spacing) * 0.5
+        } else {
+            Vector3::zero()
+        };
+
+        let mut instances = Vec::with_capacity((nx * ny * nz) as usize);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let cell = Vector3::new(x as f32, y as f32, z as f32).mul_element_wise(self.spacing) - offset;
+                    instances.push(Instance { position: self.template.position + cell, ..self.template });
+                }
+            }
+        }
+
+        instances
+    }
+}
+
+impl VolumeScatter {
+    pub fn new(bounds: Aabb, count: u32, seed: u64) -> Self {
+        Self { bounds, count, seed, variation: ScatterVariation::default(), template: Instance::default() }
+    }
+}
+
+impl InstanceLayout for VolumeScatter {
+    fn instances(&self) -> Vec<Instance> {
+        let mut random = Random::new(self.seed);
+
+        (0..self.count).map(|_| {
+            let position = Vector3::new(
+                random.range(self.bounds.min.x, self.bounds.max.x),
+                random.range(self.bounds.min.y, self.bounds.max.y),
+                random.range(self.bounds.min.z, self.bounds.max.z),
+            );
+
+            self.variation.apply(&mut random, Instance { position, ..self.template })
+        }).collect()
+    }
+}
+
+impl SurfaceScatter {
+    pub fn new(mesh: &MeshComponent, count: u32, seed: u64) -> Self {
+        Self::from_triangles(&mesh.vertices.positions(), &mesh.indices, count, seed)
+    }
+
+    // Same as `new()' for a triangle list that isn't (or isn't yet) a mesh. Triangles with
+    // indices out of range are left out.
+    pub fn from_triangles(positions: &[[f32; 3]], indices: &[u32], count: u32, seed: u64) -> Self {
+        let triangles = indices.chunks_exact(3)
+            .filter_map(|triangle| {
+                let corner = |index: u32| positions.get(index as usize).copied().map(Vector3::from);
+                Some([corner(triangle[0])?, corner(triangle[1])?, corner(triangle[2])?])
+            })
+            .collect();
+
+        Self {
+            triangles,
+            count,
+            seed,
+            align_to_normal: false,
+            variation: ScatterVariation::default(),
+            template: Instance::default(),
+        }
+    }
+}
+
+impl InstanceLayout for SurfaceScatter {
+    // Meshes without any area give no instances.
+    fn instances(&self) -> Vec<Instance> {
+        let cumulative_areas = self.triangles.iter()
+            .scan(0.0, |total, [a, b, c]| {
+                *total += (b - a).cross(c - a).magnitude() * 0.5;
+                Some(*total)
+            })
+            .collect::<Vec<f32>>();
+
+        let total_area = match cumulative_areas.last() {
+            Some(&area) if area > 0.0 => area,
+            _ => return vec![],
+        };
+
+        let mut random = Random::new(self.seed);
+
+        (0..self.count).map(|_| {
+            let target = random.range(0.0, total_area);
+            let index = cumulative_areas.partition_point(|&area| area <= target).min(self.triangles.len() - 1);
+            let [a, b, c] = self.triangles[index];
+
+            // Uniform over the triangle, folding the far half of the parallelogram back.
+            let (mut u, mut v) = (random.next_f32(), random.next_f32());
+            if u + v > 1.0 {
+                u = 1.0 - u;
+                v = 1.0 - v;
+            }
+            let position = a + (b - a) * u + (c - a) * v;
+
+            let rotation = if self.align_to_normal {
+                let normal = (b - a).cross(c - a);
+                if normal.magnitude2() > 0.0 {
+                    Quaternion::from_arc(Vector3::unit_y(), normal.normalize(), None) * self.template.rotation
+                } else {
+                    self.template.rotation
+                }
+            } else {
+                self.template.rotation
+            };
+
+            self.variation.apply(&mut random, Instance {
+                position: self.template.position + position,
+                rotation,
+                ..self.template
+            })
+        }).collect()
+    }
+}
+
+impl ScatterVariation {
+    fn apply(&self, random: &mut Random, mut instance: Instance) -> Instance {
+        if self.random_yaw {
+            let yaw = Rad::from(Deg(random.range(0.0, 360.0)));
+            instance.rotation = instance.rotation * Quaternion::from_angle_y(yaw);
+        }
+
+        instance.scale *= random.range(self.scale.0, self.scale.1);
+        instance
+    }
+}
+
+// PCG32, small and deterministic, which is all scattering needs.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        let mut random = Self { state: 0 };
+        random.next_u32();
+        random.state = random.state.wrapping_add(seed);
+        random.next_u32();
+
+        random
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        xorshifted.rotate_right((state >> 59) as u32)
+    }
+
+    // In 0..1.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use super::*;
+
+    fn bounds() -> Aabb {
+        Aabb { min: Point3::new(-1.0, 0.0, 2.0), max: Point3::new(3.0, 0.5, 4.0) }
+    }
+
+    // A 2x2 square in the xz plane, facing +y.
+    fn square(seed: u64) -> SurfaceScatter {
+        let positions = [[0.0, 0.0, 0.0], [0.0, 0.0, 2.0], [2.0, 0.0, 2.0], [2.0, 0.0, 0.0]];
+        SurfaceScatter::from_triangles(&positions, &[0, 1, 2, 0, 2, 3], 64, seed)
+    }
+
+    #[test]
+    fn volume_scatter_is_deterministic() {
+        let scatter = VolumeScatter { variation: ScatterVariation { random_yaw: true, scale: (0.5, 2.0) }, ..VolumeScatter::new(bounds(), 64, 7) };
+
+        assert_eq!(scatter.instances(), scatter.instances());
+        assert_ne!(scatter.instances(), VolumeScatter { seed: 8, ..scatter }.instances());
+    }
+
+    #[test]
+    fn volume_scatter_stays_in_bounds() {
+        let bounds = bounds();
+        let instances = VolumeScatter::new(bounds, 256, 1).instances();
+
+        assert_eq!(instances.len(), 256);
+        for instance in instances {
+            let position = instance.position;
+            assert!((bounds.min.x..=bounds.max.x).contains(&position.x));
+            assert!((bounds.min.y..=bounds.max.y).contains(&position.y));
+            assert!((bounds.min.z..=bounds.max.z).contains(&position.z));
+        }
+    }
+
+    #[test]
+    fn scatter_variation_stays_in_range() {
+        let scatter = VolumeScatter { variation: ScatterVariation { random_yaw: false, scale: (0.5, 2.0) }, ..VolumeScatter::new(bounds(), 256, 3) };
+
+        for instance in scatter.instances() {
+            assert!((0.5..=2.0).contains(&instance.scale.x));
+            assert_eq!(instance.scale.x, instance.scale.y);
+            assert_eq!(instance.scale.x, instance.scale.z);
+            assert_eq!(instance.rotation, Quaternion::one());
+        }
+    }
+
+    #[test]
+    fn surface_scatter_is_deterministic() {
+        assert_eq!(square(7).instances(), square(7).instances());
+        assert_ne!(square(7).instances(), square(8).instances());
+    }
+
+    #[test]
+    fn surface_scatter_stays_on_the_surface() {
+        let instances = square(1).instances();
+
+        assert_eq!(instances.len(), 64);
+        for instance in instances {
+            let position = instance.position;
+            assert_eq!(position.y, 0.0);
+            assert!((0.0..=2.0).contains(&position.x));
+            assert!((0.0..=2.0).contains(&position.z));
+        }
+    }
+
+    #[test]
+    fn surface_scatter_aligns_to_normals() {
+        // The square facing +x instead.
+        let positions = [[0.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 2.0, 2.0], [0.0, 0.0, 2.0]];
+        let scatter = SurfaceScatter { align_to_normal: true, ..SurfaceScatter::from_triangles(&positions, &[0, 1, 2, 0, 2, 3], 16, 1) };
+
+        for instance in scatter.instances() {
+            let up = instance.rotation.rotate_vector(Vector3::unit_y());
+            assert!((up - Vector3::unit_x()).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn surfaces_without_area_give_nothing() {
+        let degenerate = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]];
+
+        assert!(SurfaceScatter::from_triangles(&degenerate, &[0, 1, 2], 16, 1).instances().is_empty());
+        assert!(SurfaceScatter::from_triangles(&[], &[], 16, 1).instances().is_empty());
+        // Out of range indices are dropped rather than read.
+        assert!(SurfaceScatter::from_triangles(&degenerate, &[0, 1, 5], 16, 1).instances().is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::any::Any;
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+
+use crate::ecs::scene::Scene;
+use crate::ecs::component::Component;
+use crate::ecs::component::transform::TransformComponent;
+
+// Has to match `MAX_LIGHTS' in res/shaders/h_lighting.frag.
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    Directional,
+    // Light fades out completely at `range'.
+    Point { range: f32 },
+    // Full intensity within `inner_angle' of the spot's axis, none past `outer_angle'.
+    Spot { range: f32, inner_angle_degrees: f32, outer_angle_degrees: f32 },
+}
+
+// Lights are placed by their object's transform: they sit at its origin and shine down
+// its local -Z axis (as in glTF). Without a transform they shine down the world's -Z.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightComponent {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Component for LightComponent {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl LightComponent {
+    pub fn directional(color: [f32; 3], intensity: f32) -> Self {
+        Self { kind: LightKind::Directional, color, intensity }
+    }
+
+    pub fn point(color: [f32; 3], intensity: f32, range: f32) -> Self {
+        Self { kind: LightKind::Point { range }, color, intensity }
+    }
+
+    pub fn spot(color: [f32; 3], intensity: f32, range: f32, inner_angle_degrees: f32, outer_angle_degrees: f32) -> Self {
+        Self { kind: LightKind::Spot { range, inner_angle_degrees, outer_angle_degrees }, color, intensity }
+    }
+
+    pub fn to_raw(&self, world: &Matrix4<f32>) -> LightRaw {
+        let position = world * Vector4::unit_w();
+        let direction = (world * -Vector4::unit_z()).truncate();
+        let direction = if direction.magnitude2() > 0.0 { direction.normalize() } else { -cgmath::Vector3::unit_z() };
+
+        let (kind, range, cone) = match self.kind {
+            LightKind::Directional => (0.0, 0.0, [0.0; 4]),
+            LightKind::Point { range } => (1.0, range, [0.0; 4]),
+            LightKind::Spot { range, inner_angle_degrees, outer_angle_degrees } => {
+                let inner = cgmath::Deg(inner_angle_degrees).cos();
+                let outer = cgmath::Deg(outer_angle_degrees).cos();
+
+                (2.0, range, [inner, outer, 0.0, 0.0])
+            },
+        };
+
+        LightRaw {
+            position: [position.x, position.y, position.z, kind],
+            direction: [direction.x, direction.y, direction.z, range],
+            color: [self.color[0], self.color[1], self.color[2], self.intensity],
+            cone,
+        }
+    }
+}
+
+// Everything is a vec4 so the layout is the same under std140 rules.
+#[repr(C)]
+#[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightRaw {
+    // w: 0 for directional, 1 for point and 2 for spot lights.
+    position: [f32; 4],
+    // w: range.
+    direction: [f32; 4],
+    // w: intensity.
+    color: [f32; 4],
+    // x/y: cosines of the inner/outer spot angles, z: 1 for the shadow caster.
+    cone: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    count: [u32; 4],
+    ambient: [f32; 4],
+    lights: [LightRaw; MAX_LIGHTS],
+}
+
+impl LightUniform {
+    pub fn new() -> Self {
+        Self {
+            count: [0; 4],
+            ambient: [0.0; 4],
+            lights: [LightRaw::default(); MAX_LIGHTS],
+        }
+    }
+
+    // Collects the scene's lights. Anything past `MAX_LIGHTS' is ignored. The first
+    // directional light casts the scene's shadows, it gets flagged in `cone.z'.
+    pub fn update_lights(&mut self, scene: &Scene) {
+        let mut count = 0;
+        let mut shadow_caster = false;
+
+        for (entity, light) in scene.query::<LightComponent>().take(MAX_LIGHTS) {
+            let world = scene.component::<TransformComponent>(entity)
+                .map_or_else(Matrix4::identity, |transform| transform.world);
+
+            self.lights[count] = light.to_raw(&world);
+            if light.kind == LightKind::Directional && !shadow_caster {
+                self.lights[count].cone[2] = 1.0;
+                shadow_caster = true;
+            }
+            count += 1;
+        }
+
+        self.count = [count as u32, 0, 0, 0];
+        self.ambient = [scene.ambient_light[0], scene.ambient_light[1], scene.ambient_light[2], 1.0];
+    }
+
+    // Direction the shadow casting light shines in, if there is one.
+    pub fn shadow_direction(&self) -> Option<Vector3<f32>> {
+        self.lights[..self.count[0] as usize].iter()
+            .find(|light| light.cone[2] > 0.0)
+            .map(|light| Vector3::new(light.direction[0], light.direction[1], light.direction[2]))
+    }
+}
+
+impl Default for LightUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,29 +1,42 @@
 use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
 use wgpu::util::DeviceExt;
 
-use crate::vertex::PureVertex;
+use crate::vertex::{MeshVertices, PureVertex, VertexLayout};
+use crate::material::MaterialHandle;
+use crate::culling::Aabb;
 use crate::ecs::component::Component;
 
+// Drawn once per instance of its entity's `InstanceComponent' (see `Scene::query2()').
 pub struct MeshComponent {
     pub desc: String,
-    pub vertices: Vec<PureVertex>,
+    pub vertices: MeshVertices,
     pub indices: Vec<u32>,
     pub vertex_buffer: wgpu::Buffer,
     pub num_vertices: u32,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
-    pub parent_index: usize,
-    pub instance_component_index: usize,
+    // Object space bounds of `vertices', kept up to date by `update_buffers()'.
+    pub bounds: Aabb,
+    // Only used by layouts with texture coordinates. Falls back to the default material.
+    pub material: Option<MaterialHandle>,
+    // Different for every set of buffers, so copies of them (see `IndirectDraws') can tell
+    // when they're out of date.
+    pub revision: u64,
 }
 
 impl Component for MeshComponent {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
 }
 
 impl MeshComponent {
-    pub fn default(device: &wgpu::Device, parent_index: usize, instance_component_index: usize) -> Self {
+    pub fn default(device: &wgpu::Device) -> Self {
         let vertices = vec![
             /* PureVertex { position: [-0.0868241, 0.49240386, 0.0], color: [0.5, 1.0, 1.0] },
             PureVertex { position: [-0.49513406, 0.06958647, 0.0], color: [1.0, 0.0, 0.5] },
@@ -76,20 +89,22 @@ impl MeshComponent {
             21, 22, 23,
         ];
 
-        Self::new("DEFAULT".to_owned(), device, vertices, indices, parent_index, instance_component_index)
+        Self::new("DEFAULT".to_owned(), device, vertices, indices)
     }
 
     pub fn empty(device: &wgpu::Device) -> Self {
-        Self::new("EMPTY".to_owned(), device, vec![], vec![], 0, 0)
+        Self::new("EMPTY".to_owned(), device, Vec::<PureVertex>::new(), vec![])
     }
 
-    pub fn new(desc: String, device: &wgpu::Device, vertices: Vec<PureVertex>, indices: Vec<u32>, parent_index: usize, instance_component_index: usize) -> Self {
+    pub fn new<V: Into<MeshVertices>>(desc: String, device: &wgpu::Device, vertices: V, indices: Vec<u32>) -> Self {
+        let vertices = vertices.into();
         let buffers = Self::generate_buffers("UNINIT".to_owned(), &vertices, &indices, device);
         let vertex_buffer = buffers.0;
         let index_buffer = buffers.1;
 
         let num_vertices = vertices.len() as u32;
         let num_indices = indices.len() as u32;
+        let bounds = Aabb::from_points(&vertices.positions());
 
         Self {
             desc,
@@ -99,23 +114,37 @@ impl MeshComponent {
             num_vertices,
             index_buffer,
             num_indices,
-            parent_index,
-            instance_component_index,
+            bounds,
+            material: None,
+            revision: next_revision(),
         }
     }
 
+    pub fn with_material(mut self, material: MaterialHandle) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn layout(&self) -> VertexLayout {
+        self.vertices.layout()
+    }
+
     pub fn update_buffers(&mut self, device: &wgpu::Device) {
         let buffers = Self::generate_buffers(self.desc.clone(), &self.vertices, &self.indices, device);
 
         self.vertex_buffer = buffers.0;
         self.index_buffer = buffers.1;
+        self.num_vertices = self.vertices.len() as u32;
+        self.num_indices = self.indices.len() as u32;
+        self.bounds = Aabb::from_points(&self.vertices.positions());
+        self.revision = next_revision();
     }
 
-    fn generate_buffers(desc: String, vertices: &Vec<PureVertex>, indices: &Vec<u32>, device: &wgpu::Device) -> (wgpu::Buffer, wgpu::Buffer) {
+    fn generate_buffers(desc: String, vertices: &MeshVertices, indices: &[u32], device: &wgpu::Device) -> (wgpu::Buffer, wgpu::Buffer) {
         let vertex_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some(format!("Vertex Buffer ({})", desc).as_str()),
-                contents: bytemuck::cast_slice(&vertices),
+                contents: vertices.as_bytes(),
                 usage: wgpu::BufferUsages::VERTEX,
             }
         );
@@ -123,12 +152,17 @@ impl MeshComponent {
         let index_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some(format!("Index Buffer ({})", desc).as_str()),
-                contents: bytemuck::cast_slice(&indices),
+                contents: bytemuck::cast_slice(indices),
                 usage: wgpu::BufferUsages::INDEX,
             }
         );
 
-        return (vertex_buffer, index_buffer);
+        (vertex_buffer, index_buffer)
     }
 }
 
+fn next_revision() -> u64 {
+    static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
@@ -1,8 +1,12 @@
 pub mod mesh;
 pub mod instance;
+pub mod instance_layout;
+pub mod transform;
+pub mod light;
 
 use std::any::Any;
 
-pub trait Component {
+pub trait Component: Any {
     fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
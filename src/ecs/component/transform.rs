@@ -0,0 +1,96 @@
+use std::any::Any;
+use std::collections::HashMap;
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Quaternion, Vector3};
+
+use crate::ecs::object::Entity;
+use crate::ecs::scene::Scene;
+use crate::ecs::system::{System, SystemContext};
+use crate::ecs::component::Component;
+
+pub struct TransformComponent {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    // Local matrix combined with every ancestor's, written by `TransformPropagation'.
+    pub world: Matrix4<f32>,
+}
+
+impl Component for TransformComponent {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Default for TransformComponent {
+    fn default() -> Self {
+        Self::new(Vector3::zero(), Quaternion::one(), Vector3::new(1.0, 1.0, 1.0))
+    }
+}
+
+impl TransformComponent {
+    pub fn new(translation: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Self {
+        let mut transform = Self {
+            translation,
+            rotation,
+            scale,
+            world: Matrix4::identity(),
+        };
+
+        transform.world = transform.local_matrix();
+
+        transform
+    }
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self::new(translation, Quaternion::one(), Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn local_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+// Walks the object hierarchy from its roots and recomputes every transform's world
+// matrix. Objects without a transform pass their parent's matrix straight through.
+pub struct TransformPropagation;
+
+impl System for TransformPropagation {
+    fn run(&mut self, scene: &mut Scene, _context: &SystemContext) {
+        propagate_transforms(scene);
+    }
+}
+
+pub fn propagate_transforms(scene: &mut Scene) {
+    let mut stack = scene.objects.iter()
+        .filter(|(_, object)| object.parent.is_none())
+        .map(|(entity, _)| (entity, Matrix4::identity()))
+        .collect::<Vec<(Entity, Matrix4<f32>)>>();
+
+    while let Some((entity, parent_world)) = stack.pop() {
+        let world = match scene.component_mut::<TransformComponent>(entity) {
+            Some(transform) => {
+                transform.world = parent_world * transform.local_matrix();
+                transform.world
+            },
+            None => parent_world,
+        };
+
+        if let Some(object) = scene.object(entity) {
+            stack.extend(object.children.iter().map(|child| (*child, world)));
+        }
+    }
+}
+
+// World matrices of every entity that has a transform.
+pub fn world_matrices(scene: &Scene) -> HashMap<Entity, Matrix4<f32>> {
+    scene.query::<TransformComponent>()
+        .map(|(entity, transform)| (entity, transform.world))
+        .collect()
+}
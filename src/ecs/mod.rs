@@ -1,4 +1,8 @@
+pub mod arena;
 pub mod object;
 pub mod scene;
+pub mod storage;
+pub mod system;
+pub mod serialize;
 pub mod component;
 
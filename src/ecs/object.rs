@@ -1,11 +1,30 @@
+use std::any::TypeId;
+
+use crate::ecs::arena::Handle;
+
+pub type Entity = Handle<Object>;
+
 pub struct Object {
-    pub components: Vec<usize>,
+    // Component types this object has entries for, so despawning only touches the
+    // storages it's actually in.
+    pub components: Vec<TypeId>,
+    // Kept in sync by `Scene::set_parent()'.
+    pub parent: Option<Entity>,
+    pub children: Vec<Entity>,
+}
+
+impl Default for Object {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Object {
     pub fn new() -> Self {
         Self {
             components: vec![],
+            parent: None,
+            children: vec![],
         }
     }
 }
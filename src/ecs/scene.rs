@@ -1,17 +1,376 @@
-use crate::ecs::object::Object;
+use std::any::TypeId;
+use std::collections::HashMap;
+
+use crate::material::{Material, MaterialHandle};
+use crate::ecs::arena::Arena;
+use crate::ecs::object::{Entity, Object};
+use crate::ecs::storage::{AnyStorage, ComponentHandle, ComponentStorage};
 use crate::ecs::component::Component;
 
+pub const DEFAULT_AMBIENT_LIGHT: [f32; 3] = [0.05, 0.05, 0.05];
+
 pub struct Scene {
-    pub objects: Vec<Object>,
-    pub components: Vec<Box<dyn Component>>,
+    pub objects: Arena<Object>,
+    // Shared between meshes, see `MeshComponent::material'.
+    pub materials: Arena<Material>,
+    // Added to every lit surface, regardless of the lights in the scene.
+    pub ambient_light: [f32; 3],
+    storages: HashMap<TypeId, Box<dyn AnyStorage>>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scene {
     pub fn new() -> Self {
         Self {
-            objects: vec![],
-            components: vec![],
+            objects: Arena::new(),
+            materials: Arena::new(),
+            ambient_light: DEFAULT_AMBIENT_LIGHT,
+            storages: HashMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        self.objects.insert(Object::new())
+    }
+
+    // Spawns a new object underneath `parent', or returns None if the parent doesn't exist.
+    pub fn spawn_child(&mut self, parent: Entity) -> Option<Entity> {
+        if !self.objects.contains(parent) {
+            return None;
+        }
+
+        let child = self.spawn();
+        self.set_parent(child, Some(parent));
+
+        Some(child)
+    }
+
+    // Removes the object, its children (recursively) and every component attached to
+    // them. Returns false if the entity was already gone.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.set_parent(entity, None) {
+            return false;
         }
+
+        let mut stack = vec![entity];
+        while let Some(entity) = stack.pop() {
+            let object = match self.objects.remove(entity) {
+                Some(object) => object,
+                None => continue,
+            };
+
+            for type_id in object.components {
+                if let Some(storage) = self.storages.get_mut(&type_id) {
+                    storage.remove_entity(entity);
+                }
+            }
+
+            stack.extend(object.children);
+        }
+
+        true
+    }
+
+    // Moves `child' underneath `parent' (or to the top level if None). Fails if either
+    // entity is gone, or if `parent' is `child' or one of its descendants.
+    pub fn set_parent(&mut self, child: Entity, parent: Option<Entity>) -> bool {
+        if !self.objects.contains(child) {
+            return false;
+        }
+
+        if let Some(parent) = parent {
+            let mut ancestor = Some(parent);
+            while let Some(current) = ancestor {
+                if current == child {
+                    return false;
+                }
+
+                ancestor = match self.objects.get(current) {
+                    Some(object) => object.parent,
+                    None => return false,
+                };
+            }
+        }
+
+        let old_parent = self.objects.get_mut(child)
+            .and_then(|object| std::mem::replace(&mut object.parent, parent));
+        if let Some(old_parent) = old_parent.and_then(|p| self.objects.get_mut(p)) {
+            old_parent.children.retain(|c| *c != child);
+        }
+
+        if let Some(new_parent) = parent.and_then(|p| self.objects.get_mut(p)) {
+            new_parent.children.push(child);
+        }
+
+        true
+    }
+
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.objects.get(entity)?.parent
+    }
+
+    pub fn children(&self, entity: Entity) -> &[Entity] {
+        match self.objects.get(entity) {
+            Some(object) => &object.children,
+            None => &[],
+        }
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialHandle {
+        self.materials.insert(material)
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.materials.get(handle)
+    }
+
+    pub fn material_mut(&mut self, handle: MaterialHandle) -> Option<&mut Material> {
+        self.materials.get_mut(handle)
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.objects.contains(entity)
+    }
+
+    pub fn object(&self, entity: Entity) -> Option<&Object> {
+        self.objects.get(entity)
+    }
+
+    pub fn object_mut(&mut self, entity: Entity) -> Option<&mut Object> {
+        self.objects.get_mut(entity)
+    }
+
+    // Attaches `component' to `entity', or returns None (dropping the component) if the
+    // entity doesn't exist. Adding a second component of the same type keeps both, the
+    // returned handle tells them apart.
+    pub fn add_component<T: Component>(&mut self, entity: Entity, component: T) -> Option<ComponentHandle<T>> {
+        let object = self.objects.get_mut(entity)?;
+
+        let type_id = TypeId::of::<T>();
+        if !object.components.contains(&type_id) {
+            object.components.push(type_id);
+        }
+
+        let handle = self.storages.entry(type_id)
+            .or_insert_with(|| Box::new(ComponentStorage::<T>::new()))
+            .as_any_mut()
+            .downcast_mut::<ComponentStorage<T>>()
+            .expect("Component storage registered under the wrong type!")
+            .insert(entity, component);
+
+        Some(handle)
+    }
+
+    // Detaches only the component `handle' refers to.
+    pub fn remove_component<T: Component>(&mut self, handle: ComponentHandle<T>) -> Option<T> {
+        let storage = self.storage_mut::<T>()?;
+        let component = storage.remove_one(handle)?;
+        let last_of_type = !storage.contains(handle.entity);
+
+        if let (true, Some(object)) = (last_of_type, self.objects.get_mut(handle.entity)) {
+            object.components.retain(|type_id| *type_id != TypeId::of::<T>());
+        }
+
+        Some(component)
+    }
+
+    // Detaches every `T' from `entity'.
+    pub fn remove_components<T: Component>(&mut self, entity: Entity) -> Vec<T> {
+        let object = match self.objects.get_mut(entity) {
+            Some(object) => object,
+            None => return vec![],
+        };
+
+        object.components.retain(|type_id| *type_id != TypeId::of::<T>());
+
+        match self.storage_mut::<T>() {
+            Some(storage) => storage.remove(entity),
+            None => vec![],
+        }
+    }
+
+    pub fn component<T: Component>(&self, entity: Entity) -> Option<&T> {
+        self.storage::<T>()?.get(entity)
+    }
+
+    pub fn component_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.storage_mut::<T>()?.get_mut(entity)
+    }
+
+    pub fn components<T: Component>(&self, entity: Entity) -> impl Iterator<Item = &T> {
+        self.storage::<T>().into_iter().flat_map(move |storage| storage.get_all(entity))
+    }
+
+    pub fn component_handles<T: Component>(&self, entity: Entity) -> impl Iterator<Item = ComponentHandle<T>> + '_ {
+        self.storage::<T>().into_iter().flat_map(move |storage| storage.handles(entity))
+    }
+
+    pub fn component_by_handle<T: Component>(&self, handle: ComponentHandle<T>) -> Option<&T> {
+        self.storage::<T>()?.get_by_handle(handle)
+    }
+
+    pub fn component_by_handle_mut<T: Component>(&mut self, handle: ComponentHandle<T>) -> Option<&mut T> {
+        self.storage_mut::<T>()?.get_by_handle_mut(handle)
+    }
+
+    pub fn storage<T: Component>(&self) -> Option<&ComponentStorage<T>> {
+        self.storages.get(&TypeId::of::<T>())?
+            .as_any()
+            .downcast_ref::<ComponentStorage<T>>()
+    }
+
+    pub fn storage_mut<T: Component>(&mut self) -> Option<&mut ComponentStorage<T>> {
+        self.storages.get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<ComponentStorage<T>>()
+    }
+
+    // Every `T' in the scene along with the entity owning it.
+    pub fn query<T: Component>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.storage::<T>().into_iter().flat_map(|storage| storage.iter())
+    }
+
+    pub fn query_mut<T: Component>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.storage_mut::<T>().into_iter().flat_map(|storage| storage.iter_mut())
+    }
+
+    // Every `A' whose entity also has a `B', paired with that entity's first `B' only: an
+    // entity with two `A's and two `B's gives two items, both with the first `B'. That's how
+    // meshes find their instances, every mesh of an entity shares its first
+    // `InstanceComponent'.
+    pub fn query2<A: Component, B: Component>(&self) -> impl Iterator<Item = (Entity, &A, &B)> {
+        let others = self.storage::<B>();
+
+        self.query::<A>().filter_map(move |(entity, a)| {
+            others?.get(entity).map(|b| (entity, a, b))
+        })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Marker(u32);
+
+    impl Component for Marker {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Other;
+
+    impl Component for Other {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn components_of_despawned_entities_are_gone() {
+        let mut scene = Scene::new();
+        let entity = scene.spawn();
+        scene.add_component(entity, Marker(1));
+
+        assert!(scene.despawn(entity));
+        assert!(!scene.despawn(entity));
+        assert_eq!(scene.add_component(entity, Marker(2)), None);
+        assert_eq!(scene.query::<Marker>().count(), 0);
+    }
+
+    #[test]
+    fn remove_component_only_takes_the_handles_component() {
+        let mut scene = Scene::new();
+        let entity = scene.spawn();
+        let first = scene.add_component(entity, Marker(1)).unwrap();
+        let second = scene.add_component(entity, Marker(2)).unwrap();
+
+        assert_eq!(scene.remove_component(first), Some(Marker(1)));
+        assert_eq!(scene.remove_component(first), None);
+        assert_eq!(scene.component::<Marker>(entity), Some(&Marker(2)));
+        assert!(scene.object(entity).unwrap().components.contains(&TypeId::of::<Marker>()));
+
+        scene.remove_component(second);
+        assert!(!scene.object(entity).unwrap().components.contains(&TypeId::of::<Marker>()));
+    }
+
+    #[test]
+    fn query2_pairs_with_the_first_other_component() {
+        let mut scene = Scene::new();
+        let both = scene.spawn();
+        let marker_only = scene.spawn();
+        scene.add_component(both, Marker(1));
+        scene.add_component(both, Marker(2));
+        scene.add_component(both, Other);
+        scene.add_component(marker_only, Marker(3));
+
+        let pairs = scene.query2::<Marker, Other>().map(|(entity, marker, _)| (entity, marker.0)).collect::<Vec<_>>();
+        assert_eq!(pairs, vec![(both, 1), (both, 2)]);
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut scene = Scene::new();
+        let root = scene.spawn();
+        let child = scene.spawn_child(root).unwrap();
+        let grandchild = scene.spawn_child(child).unwrap();
+
+        assert!(!scene.set_parent(root, Some(root)));
+        assert!(!scene.set_parent(root, Some(grandchild)));
+        assert!(!scene.set_parent(child, Some(grandchild)));
+        assert_eq!(scene.parent(root), None);
+        assert_eq!(scene.parent(child), Some(root));
+    }
+
+    #[test]
+    fn set_parent_moves_children_between_parents() {
+        let mut scene = Scene::new();
+        let first = scene.spawn();
+        let second = scene.spawn();
+        let child = scene.spawn_child(first).unwrap();
+
+        assert!(scene.set_parent(child, Some(second)));
+        assert!(scene.children(first).is_empty());
+        assert_eq!(scene.children(second), &[child]);
+
+        assert!(scene.set_parent(child, None));
+        assert!(scene.children(second).is_empty());
+        assert_eq!(scene.parent(child), None);
+    }
+
+    #[test]
+    fn despawn_takes_descendants_and_their_components() {
+        let mut scene = Scene::new();
+        let root = scene.spawn();
+        let child = scene.spawn_child(root).unwrap();
+        let grandchild = scene.spawn_child(child).unwrap();
+        let sibling = scene.spawn();
+        scene.add_component(grandchild, Marker(1));
+        scene.add_component(sibling, Marker(2));
+
+        assert!(scene.despawn(child));
+
+        assert!(scene.is_alive(root));
+        assert!(!scene.is_alive(child));
+        assert!(!scene.is_alive(grandchild));
+        assert!(scene.children(root).is_empty());
+        assert_eq!(scene.query::<Marker>().map(|(entity, _)| entity).collect::<Vec<_>>(), vec![sibling]);
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use anyhow::{bail, Context, Result};
+use cgmath::{Deg, Quaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::texture::Texture;
+use crate::material::{AlphaMode, Material, MaterialHandle, MaterialLayouts, PbrProperties, ShadingModel, TextureSource, TextureSources};
+use crate::vertex::MeshVertices;
+use crate::ecs::object::Entity;
+use crate::ecs::scene::Scene;
+use crate::ecs::component::mesh::MeshComponent;
+use crate::ecs::component::instance::{Instance, InstanceComponent};
+use crate::ecs::component::transform::TransformComponent;
+use crate::ecs::component::light::LightComponent;
+
+// On-disk form of a scene, stored as RON. GPU resources aren't saved, they're rebuilt
+// from the CPU-side data when loading. Textures are saved as the paths they were loaded
+// from.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub camera: Option<CameraData>,
+    #[serde(default)]
+    pub ambient_light: Option<[f32; 3]>,
+    #[serde(default)]
+    pub materials: Vec<MaterialData>,
+    #[serde(default)]
+    pub objects: Vec<ObjectData>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CameraData {
+    pub position: [f32; 3],
+    pub yaw_degrees: f32,
+    pub pitch_degrees: f32,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ObjectData {
+    // Index of the parent within `SceneFile::objects'.
+    #[serde(default)]
+    pub parent: Option<usize>,
+    #[serde(default)]
+    pub transform: Option<TransformData>,
+    #[serde(default)]
+    pub meshes: Vec<MeshData>,
+    #[serde(default)]
+    pub instances: Vec<InstanceData>,
+    #[serde(default)]
+    pub lights: Vec<LightComponent>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransformData {
+    pub translation: [f32; 3],
+    // Stored as (x, y, z, w).
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MeshData {
+    pub desc: String,
+    pub vertices: MeshVertices,
+    pub indices: Vec<u32>,
+    // Index of the material within `SceneFile::materials'.
+    #[serde(default)]
+    pub material: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MaterialData {
+    pub name: String,
+    pub shading: ShadingModel,
+    #[serde(default)]
+    pub alpha_mode: AlphaMode,
+    // The base colour factor of PBR materials.
+    pub tint: [f32; 4],
+    // Only used by PBR materials, which get glTF's defaults without it.
+    #[serde(default)]
+    pub pbr: Option<PbrData>,
+    #[serde(default)]
+    pub textures: TextureSources,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PbrData {
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InstanceData {
+    pub num_instances_per_row: u32,
+    pub instance_displacement: [f32; 3],
+    // The instances themselves, which may have been edited since they were laid out in
+    // rows. Older files only have the rows.
+    #[serde(default)]
+    pub instances: Option<Vec<SingleInstanceData>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SingleInstanceData {
+    pub position: [f32; 3],
+    // Quaternion as x, y, z, w.
+    pub rotation: [f32; 4],
+    // Files from before instances had these get the defaults.
+    #[serde(default = "one_vector")]
+    pub scale: [f32; 3],
+    #[serde(default = "white")]
+    pub tint: [f32; 4],
+    #[serde(default)]
+    pub custom: [f32; 4],
+}
+
+impl CameraData {
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            position: camera.position.into(),
+            yaw_degrees: Deg::from(camera.yaw).0,
+            pitch_degrees: Deg::from(camera.pitch).0,
+        }
+    }
+
+    pub fn to_camera(&self) -> Camera {
+        Camera::new(self.position, Deg(self.yaw_degrees), Deg(self.pitch_degrees))
+    }
+}
+
+impl TransformData {
+    pub fn from_component(transform: &TransformComponent) -> Self {
+        let rotation = transform.rotation;
+
+        Self {
+            translation: transform.translation.into(),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale: transform.scale.into(),
+        }
+    }
+
+    pub fn to_component(&self) -> TransformComponent {
+        let [x, y, z, w] = self.rotation;
+
+        TransformComponent::new(Vector3::from(self.translation), Quaternion::new(w, x, y, z), Vector3::from(self.scale))
+    }
+}
+
+impl MaterialData {
+    // Embedded textures can't be saved, they're left out with a warning.
+    pub fn from_material(material: &Material) -> Self {
+        let mut textures = material.sources.clone();
+        for source in [
+            &mut textures.base_color,
+            &mut textures.metallic_roughness,
+            &mut textures.normal,
+            &mut textures.occlusion,
+            &mut textures.emissive,
+        ] {
+            if *source == TextureSource::Embedded {
+                log::warn!("Material {} has an embedded texture, which won't be saved.", material.name);
+                *source = TextureSource::None;
+            }
+        }
+
+        Self {
+            name: material.name.clone(),
+            shading: material.shading,
+            alpha_mode: material.alpha_mode,
+            tint: material.tint,
+            pbr: material.pbr().map(|pbr| PbrData {
+                metallic_factor: pbr.metallic_factor,
+                roughness_factor: pbr.roughness_factor,
+                normal_scale: pbr.normal_scale,
+                occlusion_strength: pbr.occlusion_strength,
+                emissive_factor: pbr.emissive_factor,
+            }),
+            textures,
+        }
+    }
+
+    // `textures' caches the images already loaded, by path and colour space.
+    pub fn to_material(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_layouts: &MaterialLayouts,
+        textures: &mut HashMap<(PathBuf, bool), Rc<Texture>>,
+    ) -> Result<Material> {
+        let mut load = |source: &TextureSource, srgb: bool| load_texture(device, queue, textures, source, srgb);
+
+        let base_color = match load(&self.textures.base_color, true)? {
+            Some(texture) => texture,
+            None => Rc::new(Texture::from_color(device, queue, [255, 255, 255, 255], "white")),
+        };
+
+        let mut material = match self.shading {
+            ShadingModel::BlinnPhong => Material::new(device, &material_layouts.blinn_phong, &self.name, base_color, self.tint),
+            ShadingModel::Pbr => {
+                let factors = self.pbr.clone().unwrap_or_default();
+                let mut properties = PbrProperties::from_factors(device, queue, factors.metallic_factor, factors.roughness_factor);
+                properties.normal_scale = factors.normal_scale;
+                properties.occlusion_strength = factors.occlusion_strength;
+                properties.emissive_factor = factors.emissive_factor;
+
+                if let Some(texture) = load(&self.textures.metallic_roughness, false)? {
+                    properties.metallic_roughness = texture;
+                }
+                if let Some(texture) = load(&self.textures.normal, false)? {
+                    properties.normal = texture;
+                }
+                if let Some(texture) = load(&self.textures.occlusion, false)? {
+                    properties.occlusion = texture;
+                }
+                if let Some(texture) = load(&self.textures.emissive, true)? {
+                    properties.emissive = texture;
+                }
+
+                Material::new_pbr(device, &material_layouts.pbr, &self.name, base_color, self.tint, properties)
+            },
+        };
+
+        material.alpha_mode = self.alpha_mode;
+        material.sources = self.textures.clone();
+
+        Ok(material)
+    }
+}
+
+impl Default for PbrData {
+    fn default() -> Self {
+        Self {
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0; 3],
+        }
+    }
+}
+
+impl SingleInstanceData {
+    pub fn from_instance(instance: &Instance) -> Self {
+        let rotation = instance.rotation;
+
+        Self {
+            position: instance.position.into(),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale: instance.scale.into(),
+            tint: instance.tint,
+            custom: instance.custom,
+        }
+    }
+
+    pub fn to_instance(&self) -> Instance {
+        let [x, y, z, w] = self.rotation;
+
+        Instance {
+            position: Vector3::from(self.position),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: Vector3::from(self.scale),
+            tint: self.tint,
+            custom: self.custom,
+        }
+    }
+}
+
+impl SceneFile {
+    pub fn from_scene(scene: &Scene, camera: Option<&Camera>) -> Self {
+        let entities = scene.objects.iter().map(|(entity, _)| entity).collect::<Vec<Entity>>();
+        let indices = entities.iter().enumerate()
+            .map(|(index, entity)| (*entity, index))
+            .collect::<HashMap<Entity, usize>>();
+
+        let material_indices = scene.materials.iter().enumerate()
+            .map(|(index, (handle, _))| (handle, index))
+            .collect::<HashMap<MaterialHandle, usize>>();
+        let materials = scene.materials.iter()
+            .map(|(_, material)| MaterialData::from_material(material))
+            .collect();
+
+        let objects = entities.iter().map(|entity| {
+            ObjectData {
+                parent: scene.parent(*entity).and_then(|parent| indices.get(&parent).copied()),
+                transform: scene.component::<TransformComponent>(*entity).map(TransformData::from_component),
+                meshes: scene.components::<MeshComponent>(*entity).map(|mesh| MeshData {
+                    desc: mesh.desc.clone(),
+                    vertices: mesh.vertices.clone(),
+                    indices: mesh.indices.clone(),
+                    material: mesh.material.and_then(|material| material_indices.get(&material).copied()),
+                }).collect(),
+                instances: scene.components::<InstanceComponent>(*entity).map(|instances| InstanceData {
+                    num_instances_per_row: instances.num_instances_per_row,
+                    instance_displacement: instances.instance_displacement.into(),
+                    instances: Some(instances.instances().iter().map(SingleInstanceData::from_instance).collect()),
+                }).collect(),
+                lights: scene.components::<LightComponent>(*entity).copied().collect(),
+            }
+        }).collect();
+
+        Self {
+            camera: camera.map(CameraData::from_camera),
+            ambient_light: Some(scene.ambient_light),
+            materials,
+            objects,
+        }
+    }
+
+    pub fn to_scene(&self, device: &wgpu::Device, queue: &wgpu::Queue, material_layouts: &MaterialLayouts) -> Result<Scene> {
+        let mut scene = Scene::new();
+        if let Some(ambient_light) = self.ambient_light {
+            scene.ambient_light = ambient_light;
+        }
+
+        let mut textures = HashMap::new();
+        let materials = self.materials.iter()
+            .map(|material| {
+                let material = material.to_material(device, queue, material_layouts, &mut textures)?;
+                Ok(scene.add_material(material))
+            })
+            .collect::<Result<Vec<MaterialHandle>>>()?;
+
+        let entities = self.objects.iter().map(|_| scene.spawn()).collect::<Vec<Entity>>();
+
+        for (index, (object, entity)) in self.objects.iter().zip(entities.iter().copied()).enumerate() {
+            if let Some(parent) = object.parent {
+                match entities.get(parent) {
+                    Some(parent) if scene.set_parent(entity, Some(*parent)) => {},
+                    _ => bail!("Object {} has an invalid parent ({})!", index, parent),
+                }
+            }
+
+            if let Some(transform) = &object.transform {
+                scene.add_component(entity, transform.to_component());
+            }
+
+            for mesh in &object.meshes {
+                let mut component = MeshComponent::new(mesh.desc.clone(), device, mesh.vertices.clone(), mesh.indices.clone());
+                if let Some(material) = mesh.material {
+                    match materials.get(material) {
+                        Some(material) => component.material = Some(*material),
+                        None => bail!("Mesh {} of object {} has an invalid material ({})!", mesh.desc, index, material),
+                    }
+                }
+
+                scene.add_component(entity, component);
+            }
+
+            for instances in &object.instances {
+                let displacement = Vector3::from(instances.instance_displacement);
+                let component = match &instances.instances {
+                    Some(transforms) => {
+                        let mut component = InstanceComponent::from_instances(device, transforms.iter().map(SingleInstanceData::to_instance).collect());
+                        component.num_instances_per_row = instances.num_instances_per_row;
+                        component.instance_displacement = displacement;
+                        component
+                    },
+                    None => InstanceComponent::new(device, instances.num_instances_per_row, displacement),
+                };
+
+                scene.add_component(entity, component);
+            }
+
+            for light in &object.lights {
+                scene.add_component(entity, *light);
+            }
+        }
+
+        Ok(scene)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self> {
+        Ok(ron::from_str(source)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String> {
+        Ok(ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)
+    }
+}
+
+impl Scene {
+    pub fn save<P: AsRef<Path>>(&self, path: P, camera: Option<&Camera>) -> Result<()> {
+        std::fs::write(path, SceneFile::from_scene(self, camera).to_ron()?)?;
+
+        Ok(())
+    }
+
+    // Loads a scene along with the camera placement it was saved with, if any.
+    pub fn load<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        material_layouts: &MaterialLayouts,
+        path: P,
+    ) -> Result<(Self, Option<Camera>)> {
+        let file = SceneFile::from_ron(&std::fs::read_to_string(path)?)?;
+        let scene = file.to_scene(device, queue, material_layouts)?;
+
+        Ok((scene, file.camera.as_ref().map(CameraData::to_camera)))
+    }
+}
+
+// `None' for sources without an image file.
+fn load_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    textures: &mut HashMap<(PathBuf, bool), Rc<Texture>>,
+    source: &TextureSource,
+    srgb: bool,
+) -> Result<Option<Rc<Texture>>> {
+    let path = match source {
+        TextureSource::File(path) => path,
+        TextureSource::None | TextureSource::Embedded => return Ok(None),
+    };
+
+    if let Some(texture) = textures.get(&(path.clone(), srgb)) {
+        return Ok(Some(texture.clone()));
+    }
+
+    let image = image::open(path)
+        .with_context(|| format!("Failed to load texture {}", path.display()))?;
+    let format = if srgb { Texture::SRGB_FORMAT } else { Texture::LINEAR_FORMAT };
+    let texture = Rc::new(Texture::from_image_with_format(device, queue, &image, Some(&path.display().to_string()), format)?);
+    textures.insert((path.clone(), srgb), texture.clone());
+
+    Ok(Some(texture))
+}
+
+fn one_vector() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn white() -> [f32; 4] {
+    [1.0; 4]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vertex::ModelVertex;
+
+    fn scene_file() -> SceneFile {
+        let vertex = |position| ModelVertex { position, normal: [0.0, 1.0, 0.0], tex_coords: [0.0; 2] };
+
+        SceneFile {
+            camera: Some(CameraData { position: [0.0, 1.0, 2.0], yaw_degrees: -90.0, pitch_degrees: 10.0 }),
+            ambient_light: Some([0.1; 3]),
+            materials: vec![MaterialData {
+                name: "brick".to_owned(),
+                shading: ShadingModel::Pbr,
+                alpha_mode: AlphaMode::Blend,
+                tint: [1.0, 0.5, 0.25, 0.5],
+                pbr: Some(PbrData { metallic_factor: 0.0, roughness_factor: 0.75, ..PbrData::default() }),
+                textures: TextureSources {
+                    base_color: TextureSource::File(PathBuf::from("res/brick.png")),
+                    ..TextureSources::default()
+                },
+            }],
+            objects: vec![
+                ObjectData {
+                    transform: Some(TransformData { translation: [1.0, 2.0, 3.0], rotation: [0.0, 0.0, 0.0, 1.0], scale: [2.0; 3] }),
+                    meshes: vec![MeshData {
+                        desc: "triangle".to_owned(),
+                        vertices: MeshVertices::Model(vec![vertex([0.0; 3]), vertex([1.0, 0.0, 0.0]), vertex([0.0, 0.0, 1.0])]),
+                        indices: vec![0, 2, 1],
+                        material: Some(0),
+                    }],
+                    instances: vec![InstanceData {
+                        num_instances_per_row: 1,
+                        instance_displacement: [0.0; 3],
+                        instances: Some(vec![SingleInstanceData::from_instance(&Instance::default())]),
+                    }],
+                    ..ObjectData::default()
+                },
+                ObjectData { parent: Some(0), ..ObjectData::default() },
+            ],
+        }
+    }
+
+    #[test]
+    fn scene_files_round_trip_through_ron() {
+        let source = scene_file().to_ron().unwrap();
+        let file = SceneFile::from_ron(&source).unwrap();
+
+        assert_eq!(file.to_ron().unwrap(), source);
+
+        let material = &file.materials[0];
+        assert_eq!(material.name, "brick");
+        assert_eq!(material.shading, ShadingModel::Pbr);
+        assert_eq!(material.alpha_mode, AlphaMode::Blend);
+        assert_eq!(material.pbr.as_ref().map(|pbr| pbr.roughness_factor), Some(0.75));
+        assert_eq!(material.textures.base_color, TextureSource::File(PathBuf::from("res/brick.png")));
+        assert_eq!(file.objects[0].meshes[0].material, Some(0));
+        assert_eq!(file.objects[0].meshes[0].indices, vec![0, 2, 1]);
+        assert_eq!(file.objects[1].parent, Some(0));
+    }
+
+    #[test]
+    fn files_without_materials_still_load() {
+        let file = SceneFile::from_ron(r#"(
+            objects: [(
+                meshes: [(desc: "empty", vertices: Model([]), indices: [])],
+                instances: [(num_instances_per_row: 2, instance_displacement: (0.0, 0.0, 0.0))],
+            )],
+        )"#).unwrap();
+
+        assert!(file.materials.is_empty());
+        assert_eq!(file.objects[0].meshes[0].material, None);
+        assert!(file.objects[0].instances[0].instances.is_none());
+    }
+}
@@ -0,0 +1,299 @@
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::ecs::object::Entity;
+use crate::ecs::component::Component;
+
+// One particular component of an entity, for when the entity owns several of the same type.
+// Stops resolving once that component is removed, even if the entity gets another one.
+pub struct ComponentHandle<T> {
+    pub entity: Entity,
+    id: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// Derives would put bounds on `T', which handles don't need.
+impl<T> Clone for ComponentHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ComponentHandle<T> {}
+
+impl<T> PartialEq for ComponentHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entity == other.entity && self.id == other.id
+    }
+}
+
+impl<T> Eq for ComponentHandle<T> {}
+
+impl<T> fmt::Debug for ComponentHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ComponentHandle({:?}#{})", self.entity, self.id)
+    }
+}
+
+// Sparse set holding every component of one type. Components are packed densely so
+// queries walk a plain slice, while `sparse' maps an entity's index to its entries.
+// An entity may own several components of the same type (e.g. one mesh per glTF
+// primitive); single lookups return the first one.
+pub struct ComponentStorage<T> {
+    entities: Vec<Entity>,
+    // Never reused, so `ComponentHandle's of removed components stay dead.
+    ids: Vec<u32>,
+    components: Vec<T>,
+    sparse: Vec<Vec<usize>>,
+    next_id: u32,
+}
+
+impl<T> Default for ComponentStorage<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ComponentStorage<T> {
+    pub fn new() -> Self {
+        Self {
+            entities: vec![],
+            ids: vec![],
+            components: vec![],
+            sparse: vec![],
+            next_id: 0,
+        }
+    }
+
+    pub fn insert(&mut self, entity: Entity, component: T) -> ComponentHandle<T> {
+        if self.sparse.len() <= entity.index() {
+            self.sparse.resize_with(entity.index() + 1, Vec::new);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.sparse[entity.index()].push(self.components.len());
+        self.entities.push(entity);
+        self.ids.push(id);
+        self.components.push(component);
+
+        ComponentHandle { entity, id, _marker: PhantomData }
+    }
+
+    // Removes just the component `handle' refers to.
+    pub fn remove_one(&mut self, handle: ComponentHandle<T>) -> Option<T> {
+        let dense_index = self.handle_index(handle)?;
+
+        Some(self.swap_remove(dense_index))
+    }
+
+    // Removes every component `entity' owns in this storage.
+    pub fn remove(&mut self, entity: Entity) -> Vec<T> {
+        let mut removed = vec![];
+
+        loop {
+            let dense_index = match self.dense_indices(entity).next() {
+                Some(dense_index) => dense_index,
+                None => break,
+            };
+
+            removed.push(self.swap_remove(dense_index));
+        }
+
+        removed
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.dense_indices(entity).next().is_some()
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        let dense_index = self.dense_indices(entity).next()?;
+
+        self.components.get(dense_index)
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        let dense_index = self.dense_indices(entity).next()?;
+
+        self.components.get_mut(dense_index)
+    }
+
+    pub fn get_by_handle(&self, handle: ComponentHandle<T>) -> Option<&T> {
+        let dense_index = self.handle_index(handle)?;
+
+        self.components.get(dense_index)
+    }
+
+    pub fn get_by_handle_mut(&mut self, handle: ComponentHandle<T>) -> Option<&mut T> {
+        let dense_index = self.handle_index(handle)?;
+
+        self.components.get_mut(dense_index)
+    }
+
+    // Handles to every component `entity' owns in this storage, in insertion order (as long
+    // as none were removed in between).
+    pub fn handles(&self, entity: Entity) -> impl Iterator<Item = ComponentHandle<T>> + '_ {
+        self.dense_indices(entity)
+            .map(move |dense_index| ComponentHandle { entity, id: self.ids[dense_index], _marker: PhantomData })
+    }
+
+    pub fn get_all(&self, entity: Entity) -> impl Iterator<Item = &T> {
+        self.dense_indices(entity).map(move |dense_index| &self.components[dense_index])
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(self.components.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.entities.iter().copied().zip(self.components.iter_mut())
+    }
+
+    // Dense indices belonging to `entity'. Entries left behind by an older generation of
+    // the same slot are skipped, they get cleaned up when that entity is despawned.
+    fn dense_indices(&self, entity: Entity) -> impl Iterator<Item = usize> + '_ {
+        self.sparse.get(entity.index())
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |dense_index| self.entities[*dense_index] == entity)
+    }
+
+    fn handle_index(&self, handle: ComponentHandle<T>) -> Option<usize> {
+        let mut dense_indices = self.dense_indices(handle.entity);
+
+        dense_indices.find(|dense_index| self.ids[*dense_index] == handle.id)
+    }
+
+    fn swap_remove(&mut self, dense_index: usize) -> T {
+        let entity = self.entities[dense_index];
+        let last_index = self.components.len() - 1;
+
+        self.sparse[entity.index()].retain(|i| *i != dense_index);
+
+        if dense_index != last_index {
+            let moved = self.entities[last_index];
+            for i in self.sparse[moved.index()].iter_mut() {
+                if *i == last_index {
+                    *i = dense_index;
+                }
+            }
+        }
+
+        self.entities.swap_remove(dense_index);
+        self.ids.swap_remove(dense_index);
+        self.components.swap_remove(dense_index)
+    }
+}
+
+// Type-erased view of a `ComponentStorage<T>', so a scene can keep one per type.
+pub trait AnyStorage {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn remove_entity(&mut self, entity: Entity);
+}
+
+impl<T: Component> AnyStorage for ComponentStorage<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::arena::Arena;
+    use crate::ecs::object::Object;
+
+    fn entities<const N: usize>() -> [Entity; N] {
+        let mut objects = Arena::new();
+        [(); N].map(|_| objects.insert(Object::new()))
+    }
+
+    #[test]
+    fn swap_remove_keeps_the_moved_component_reachable() {
+        let [a, b, c] = entities();
+        let mut storage = ComponentStorage::new();
+        storage.insert(a, 'a');
+        storage.insert(b, 'b');
+        storage.insert(c, 'c');
+
+        // `c' is moved into `a''s dense slot.
+        assert_eq!(storage.remove(a), vec!['a']);
+
+        assert_eq!(storage.get(a), None);
+        assert_eq!(storage.get(b), Some(&'b'));
+        assert_eq!(storage.get(c), Some(&'c'));
+        assert_eq!(storage.iter().count(), 2);
+    }
+
+    #[test]
+    fn entities_can_own_several_components() {
+        let [a, b] = entities();
+        let mut storage = ComponentStorage::new();
+        storage.insert(a, 1);
+        storage.insert(b, 2);
+        storage.insert(a, 3);
+
+        assert_eq!(storage.get(a), Some(&1));
+        assert_eq!(storage.get_all(a).copied().collect::<Vec<_>>(), vec![1, 3]);
+
+        let mut removed = storage.remove(a);
+        removed.sort();
+        assert_eq!(removed, vec![1, 3]);
+        assert_eq!(storage.get(b), Some(&2));
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn handles_pick_out_one_component() {
+        let [a, b] = entities();
+        let mut storage = ComponentStorage::new();
+        let first = storage.insert(a, 1);
+        storage.insert(b, 2);
+        let second = storage.insert(a, 3);
+
+        assert_eq!(storage.remove_one(first), Some(1));
+        assert_eq!(storage.get_by_handle(first), None);
+        assert_eq!(storage.get_by_handle(second), Some(&3));
+        assert_eq!(storage.handles(a).collect::<Vec<_>>(), vec![second]);
+
+        // A new component doesn't bring the old handle back.
+        storage.insert(a, 4);
+        assert_eq!(storage.get_by_handle(first), None);
+    }
+
+    #[test]
+    fn stale_entities_dont_see_their_slots_new_components() {
+        let mut objects = Arena::new();
+        let stale = objects.insert(Object::new());
+        objects.remove(stale);
+        let fresh = objects.insert(Object::new());
+        assert_eq!(stale.index(), fresh.index());
+
+        let mut storage = ComponentStorage::new();
+        storage.insert(fresh, 'f');
+
+        assert!(!storage.contains(stale));
+        assert_eq!(storage.get(fresh), Some(&'f'));
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use crate::ecs::scene::Scene;
+
+// Stages run in declaration order every frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    PreUpdate,
+    Update,
+    PostUpdate,
+    // Last chance to touch the scene before it's drawn, e.g. to upload GPU data.
+    RenderPrep,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::PreUpdate, Stage::Update, Stage::PostUpdate, Stage::RenderPrep];
+}
+
+// Per-frame data handed to every system.
+pub struct SystemContext<'a> {
+    pub dt: Duration,
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+}
+
+pub trait System {
+    fn run(&mut self, scene: &mut Scene, context: &SystemContext);
+}
+
+impl<F: FnMut(&mut Scene, &SystemContext)> System for F {
+    fn run(&mut self, scene: &mut Scene, context: &SystemContext) {
+        self(scene, context)
+    }
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    stages: [Vec<Box<dyn System>>; Stage::ALL.len()],
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Systems within a stage run in the order they were added.
+    pub fn add_system<S: System + 'static>(&mut self, stage: Stage, system: S) {
+        self.stages[stage as usize].push(Box::new(system));
+    }
+
+    pub fn run(&mut self, scene: &mut Scene, context: &SystemContext) {
+        for stage in Stage::ALL {
+            self.run_stage(stage, scene, context);
+        }
+    }
+
+    pub fn run_stage(&mut self, stage: Stage, scene: &mut Scene, context: &SystemContext) {
+        for system in self.stages[stage as usize].iter_mut() {
+            system.run(scene, context);
+        }
+    }
+}
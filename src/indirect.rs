@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::culling::{scene_meshes, Frustum, MeshKey, Visibility};
+use crate::material::{AlphaMode, MaterialHandle};
+use crate::vertex::VertexLayout;
+use crate::ecs::scene::Scene;
+use crate::ecs::component::mesh::MeshComponent;
+use crate::ecs::component::instance::{InstanceComponent, InstanceRaw};
+
+const CULL_WORKGROUP_SIZE: u32 = 64;
+const ARGS_SIZE: wgpu::BufferAddress = std::mem::size_of::<DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+const INSTANCE_SIZE: wgpu::BufferAddress = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+
+// Both are needed to draw a whole batch at once: the draws of a batch share one instance
+// buffer, each starting at its own `first_instance'.
+pub const MULTI_DRAW_FEATURES: wgpu::Features = wgpu::Features::MULTI_DRAW_INDIRECT
+    .union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
+// How the scene pass issues its draws.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DrawMode {
+    // One `draw_indexed()' per run of visible instances.
+    #[default]
+    Direct,
+    // Arguments written by the CPU, one `multi_draw_indexed_indirect()' per batch of
+    // meshes sharing a pipeline and material (one `draw_indexed_indirect()' per mesh
+    // without `MULTI_DRAW_FEATURES'). Meshes are culled whole, their instances aren't.
+    Indirect,
+    // Same draws, with a compute pass culling every instance against the frustum and
+    // compacting the visible ones.
+    GpuCulled,
+}
+
+impl DrawMode {
+    pub const ALL: [DrawMode; 3] = [DrawMode::Direct, DrawMode::Indirect, DrawMode::GpuCulled];
+}
+
+// Layout `draw_indexed_indirect()' reads its arguments in.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrustumUniform {
+    planes: [[f32; 4]; 6],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullDrawUniform {
+    center: [f32; 4],
+    half_extents: [f32; 4],
+    instance_count: u32,
+    draw_index: u32,
+    // Where the mesh's visible instances go in the shared instance buffer.
+    first_instance: u32,
+    _padding: u32,
+}
+
+// Opaque meshes sharing a vertex layout and material, and so a pipeline and bind groups.
+// Their arguments follow each other in `IndirectDraws::args_buffer()'.
+pub struct IndirectBatch<'a> {
+    pub layout: VertexLayout,
+    // `None' for the default material.
+    pub material: Option<MaterialHandle>,
+    pub vertex_buffer: &'a wgpu::Buffer,
+    pub index_buffer: &'a wgpu::Buffer,
+    pub instance_buffer: &'a wgpu::Buffer,
+    // Offset of the batch's first arguments.
+    pub offset: wgpu::BufferAddress,
+    pub draw_count: u32,
+    // Room each draw has in `instance_buffer'. Without multi-draw every draw's
+    // `first_instance' is 0, the instance buffer has to be bound at the range's start.
+    pub instances: &'a [Range<u32>],
+}
+
+struct Batch {
+    layout: VertexLayout,
+    material: Option<MaterialHandle>,
+    first_draw: u32,
+    instances: Vec<Range<u32>>,
+}
+
+struct MeshDraw {
+    first_instance: u32,
+    instance_count: u32,
+    // Only for `DrawMode::GpuCulled'.
+    culling: Option<MeshCulling>,
+}
+
+struct MeshCulling {
+    // `InstanceComponent::capacity()' when created. The instance buffer only gets replaced
+    // when that changes, so the bind group is still valid as long as it's the same.
+    instance_capacity: usize,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+// Replaced by a bigger buffer (losing its contents) when it runs out of room.
+struct GrowableBuffer {
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    size: wgpu::BufferAddress,
+    buffer: wgpu::Buffer,
+}
+
+// The opaque meshes of the active scene, batched by pipeline and material with their
+// geometry and instances copied into shared buffers, plus indirect draw arguments for
+// each of them in a single buffer and what's needed to fill those in on the GPU.
+pub struct IndirectDraws {
+    mode: DrawMode,
+    multi_draw: bool,
+    args_buffer: GrowableBuffer,
+    instance_buffer: GrowableBuffer,
+    vertex_buffers: HashMap<VertexLayout, GrowableBuffer>,
+    index_buffer: GrowableBuffer,
+    // `MeshComponent::revision' of every batched mesh in order. The geometry is only
+    // uploaded again when this changes.
+    geometry: Vec<u64>,
+    frustum_buffer: wgpu::Buffer,
+    frame_bind_group_layout: wgpu::BindGroupLayout,
+    frame_bind_group: wgpu::BindGroup,
+    mesh_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    batches: Vec<Batch>,
+    draws: HashMap<MeshKey, MeshDraw>,
+}
+
+impl IndirectDraws {
+    // Batches are drawn with multi-draw when `device' has `MULTI_DRAW_FEATURES'.
+    pub fn new(device: &wgpu::Device) -> Self {
+        let args_buffer = GrowableBuffer::new(
+            device,
+            "Indirect Args Buffer",
+            wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            64 * ARGS_SIZE,
+        );
+        let instance_buffer = GrowableBuffer::new(
+            device,
+            "Indirect Instance Buffer",
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            64 * INSTANCE_SIZE,
+        );
+        let vertex_buffers = VertexLayout::ALL.iter()
+            .map(|layout| {
+                let buffer = GrowableBuffer::new(device, "Indirect Vertex Buffer", wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST, 1024);
+                (*layout, buffer)
+            })
+            .collect();
+        let index_buffer = GrowableBuffer::new(device, "Indirect Index Buffer", wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST, 1024);
+
+        let frustum_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culling Frustum Buffer"),
+            size: std::mem::size_of::<FrustumUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let frame_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_layout_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_layout_entry(1, wgpu::BufferBindingType::Storage { read_only: false }),
+                buffer_layout_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("cull_frame_bind_group_layout"),
+        });
+        let frame_bind_group = create_frame_bind_group(
+            device,
+            &frame_bind_group_layout,
+            &frustum_buffer,
+            &args_buffer.buffer,
+            &instance_buffer.buffer,
+        );
+
+        let mesh_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_layout_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_layout_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+            ],
+            label: Some("cull_mesh_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&frame_bind_group_layout, &mesh_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = create_spv_shader!(device, "../target/cull_instances.spv", "cull_instances");
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cull Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Self {
+            mode: DrawMode::Direct,
+            multi_draw: device.features().contains(MULTI_DRAW_FEATURES),
+            args_buffer,
+            instance_buffer,
+            vertex_buffers,
+            index_buffer,
+            geometry: vec![],
+            frustum_buffer,
+            frame_bind_group_layout,
+            frame_bind_group,
+            mesh_bind_group_layout,
+            pipeline,
+            batches: vec![],
+            draws: HashMap::new(),
+        }
+    }
+
+    // The mode of the last `prepare()'.
+    pub fn mode(&self) -> DrawMode {
+        self.mode
+    }
+
+    pub fn args_buffer(&self) -> &wgpu::Buffer {
+        &self.args_buffer.buffer
+    }
+
+    // Whether each batch is a single `multi_draw_indexed_indirect()'.
+    pub fn multi_draw(&self) -> bool {
+        self.multi_draw
+    }
+
+    // Writes this frame's batches and arguments for the opaque meshes of `scene'. Meshes
+    // `visibility' culled entirely get no instances (and aren't dispatched). In
+    // `DrawMode::Direct' this just drops everything.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        visibility: &Visibility,
+        frustum: &Frustum,
+        mode: DrawMode,
+    ) {
+        self.mode = mode;
+        if mode == DrawMode::Direct {
+            self.batches.clear();
+            self.draws.clear();
+            return;
+        }
+
+        // Grouped by batch, in the order the batches first show up.
+        let mut batch_keys = vec![];
+        let mut meshes = scene_meshes(scene).into_iter()
+            .filter_map(|(key, mesh_component, instance_component)| {
+                let material = mesh_component.material.and_then(|handle| scene.material(handle).map(|material| (handle, material)));
+                if material.map_or(AlphaMode::Opaque, |(_, material)| material.alpha_mode) != AlphaMode::Opaque {
+                    return None;
+                }
+
+                // Flat shaded meshes don't use their material.
+                let layout = mesh_component.layout();
+                let batch_key = (layout, material.filter(|_| layout.has_normals()).map(|(handle, _)| handle));
+                let batch = match batch_keys.iter().position(|other| *other == batch_key) {
+                    Some(batch) => batch,
+                    None => {
+                        batch_keys.push(batch_key);
+                        batch_keys.len() - 1
+                    },
+                };
+
+                Some((batch, key, mesh_component, instance_component))
+            })
+            .collect::<Vec<_>>();
+        meshes.sort_by_key(|(batch, ..)| *batch);
+
+        let mut vertex_sizes: HashMap<VertexLayout, wgpu::BufferAddress> = HashMap::new();
+        for (_, _, mesh_component, _) in meshes.iter() {
+            *vertex_sizes.entry(mesh_component.layout()).or_default() += mesh_component.vertices.as_bytes().len() as wgpu::BufferAddress;
+        }
+        let index_count = meshes.iter().map(|(_, _, mesh_component, _)| mesh_component.num_indices as wgpu::BufferAddress).sum::<wgpu::BufferAddress>();
+        let instance_count = meshes.iter().map(|(.., instance_component)| instance_component.len() as wgpu::BufferAddress).sum::<wgpu::BufferAddress>();
+
+        let mut geometry_grew = self.index_buffer.reserve(device, index_count * 4);
+        for (layout, size) in vertex_sizes {
+            if let Some(buffer) = self.vertex_buffers.get_mut(&layout) {
+                geometry_grew |= buffer.reserve(device, size);
+            }
+        }
+
+        let args_grew = self.args_buffer.reserve(device, meshes.len() as wgpu::BufferAddress * ARGS_SIZE);
+        let instances_grew = self.instance_buffer.reserve(device, instance_count * INSTANCE_SIZE);
+        if args_grew || instances_grew {
+            self.frame_bind_group = create_frame_bind_group(
+                device,
+                &self.frame_bind_group_layout,
+                &self.frustum_buffer,
+                &self.args_buffer.buffer,
+                &self.instance_buffer.buffer,
+            );
+        }
+
+        let geometry = meshes.iter().map(|(_, _, mesh_component, _)| mesh_component.revision).collect::<Vec<_>>();
+        if geometry_grew || geometry != self.geometry {
+            self.upload_geometry(queue, meshes.iter().map(|(_, _, mesh_component, _)| *mesh_component));
+            self.geometry = geometry;
+        }
+
+        let mut batches: Vec<Batch> = Vec::with_capacity(batch_keys.len());
+        let mut draws = HashMap::with_capacity(meshes.len());
+        let mut args = Vec::with_capacity(meshes.len());
+        let mut base_vertices: HashMap<VertexLayout, u32> = HashMap::new();
+        let mut first_index = 0;
+        let mut first_instance = 0;
+
+        for (index, (batch, key, mesh_component, instance_component)) in meshes.into_iter().enumerate() {
+            let index = index as u32;
+            let (layout, material) = batch_keys[batch];
+            if batches.len() <= batch {
+                batches.push(Batch { layout, material, first_draw: index, instances: vec![] });
+            }
+
+            let total = instance_component.len() as u32;
+            let visible = !visibility.instance_ranges(key, total).is_empty();
+            let instance_count = if visible { total } else { 0 };
+            let base_vertex = base_vertices.entry(layout).or_default();
+
+            args.push(DrawIndexedIndirectArgs {
+                index_count: mesh_component.num_indices,
+                // Counted up by the compute pass.
+                instance_count: if mode == DrawMode::GpuCulled { 0 } else { instance_count },
+                first_index,
+                base_vertex: *base_vertex as i32,
+                first_instance: if self.multi_draw { first_instance } else { 0 },
+            });
+            batches[batch].instances.push(first_instance..first_instance + instance_count);
+
+            *base_vertex += mesh_component.num_vertices;
+            first_index += mesh_component.num_indices;
+
+            let culling = match (mode, self.draws.remove(&key)) {
+                (DrawMode::GpuCulled, Some(MeshDraw { culling: Some(culling), .. })) if culling.instance_capacity == instance_component.capacity() => Some(culling),
+                (DrawMode::GpuCulled, _) => Some(self.create_mesh_culling(device, instance_component)),
+                _ => None,
+            };
+
+            if let Some(culling) = &culling {
+                let bounds = mesh_component.bounds;
+                let uniform = CullDrawUniform {
+                    center: bounds.center().to_homogeneous().into(),
+                    half_extents: bounds.half_extents().extend(0.0).into(),
+                    instance_count,
+                    draw_index: index,
+                    first_instance,
+                    _padding: 0,
+                };
+                queue.write_buffer(&culling.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+            }
+
+            draws.insert(key, MeshDraw { first_instance, instance_count, culling });
+            first_instance += instance_count;
+        }
+
+        // Meshes that went away are dropped along with their buffers.
+        self.batches = batches;
+        self.draws = draws;
+
+        queue.write_buffer(&self.args_buffer.buffer, 0, bytemuck::cast_slice(&args));
+        queue.write_buffer(&self.frustum_buffer, 0, bytemuck::cast_slice(&[FrustumUniform {
+            planes: frustum.planes.map(|plane| plane.into()),
+        }]));
+    }
+
+    // Records copying every batched mesh's instances into the shared instance buffer, or
+    // with `DrawMode::GpuCulled' the culling dispatches doing that for the visible ones.
+    // `scene' has to be the one last prepared.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder, scene: &Scene) {
+        match self.mode {
+            DrawMode::Direct => {},
+            DrawMode::Indirect => {
+                for (key, _, instance_component) in scene_meshes(scene) {
+                    if let Some(draw) = self.draws.get(&key).filter(|draw| draw.instance_count > 0) {
+                        encoder.copy_buffer_to_buffer(
+                            &instance_component.instance_buffer,
+                            0,
+                            &self.instance_buffer.buffer,
+                            draw.first_instance as wgpu::BufferAddress * INSTANCE_SIZE,
+                            draw.instance_count as wgpu::BufferAddress * INSTANCE_SIZE,
+                        );
+                    }
+                }
+            },
+            DrawMode::GpuCulled => {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Cull Pass"),
+                });
+
+                compute_pass.set_pipeline(&self.pipeline);
+                compute_pass.set_bind_group(0, &self.frame_bind_group, &[]);
+
+                for draw in self.draws.values() {
+                    if let (Some(culling), true) = (&draw.culling, draw.instance_count > 0) {
+                        compute_pass.set_bind_group(1, &culling.bind_group, &[]);
+                        compute_pass.dispatch_workgroups(draw.instance_count.div_ceil(CULL_WORKGROUP_SIZE), 1, 1);
+                    }
+                }
+            },
+        }
+    }
+
+    // Empty in `DrawMode::Direct'.
+    pub fn batches(&self) -> Vec<IndirectBatch<'_>> {
+        self.batches.iter()
+            .filter_map(|batch| {
+                let vertex_buffer = self.vertex_buffers.get(&batch.layout)?;
+
+                Some(IndirectBatch {
+                    layout: batch.layout,
+                    material: batch.material,
+                    vertex_buffer: &vertex_buffer.buffer,
+                    index_buffer: &self.index_buffer.buffer,
+                    instance_buffer: &self.instance_buffer.buffer,
+                    offset: batch.first_draw as wgpu::BufferAddress * ARGS_SIZE,
+                    draw_count: batch.instances.len() as u32,
+                    instances: &batch.instances,
+                })
+            })
+            .collect()
+    }
+
+    fn upload_geometry<'a>(&self, queue: &wgpu::Queue, meshes: impl Iterator<Item = &'a MeshComponent>) {
+        let mut vertices: HashMap<VertexLayout, Vec<u8>> = HashMap::new();
+        let mut indices = vec![];
+
+        for mesh_component in meshes {
+            vertices.entry(mesh_component.layout()).or_default().extend_from_slice(mesh_component.vertices.as_bytes());
+            indices.extend_from_slice(&mesh_component.indices);
+        }
+
+        for (layout, bytes) in vertices {
+            if let Some(buffer) = self.vertex_buffers.get(&layout) {
+                queue.write_buffer(&buffer.buffer, 0, &bytes);
+            }
+        }
+        queue.write_buffer(&self.index_buffer.buffer, 0, bytemuck::cast_slice(&indices));
+    }
+
+    fn create_mesh_culling(&self, device: &wgpu::Device, instance_component: &InstanceComponent) -> MeshCulling {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Draw Buffer"),
+            size: std::mem::size_of::<CullDrawUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.mesh_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instance_component.instance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("cull_mesh_bind_group"),
+        });
+
+        MeshCulling { instance_capacity: instance_component.capacity(), uniform_buffer, bind_group }
+    }
+}
+
+impl GrowableBuffer {
+    fn new(device: &wgpu::Device, label: &'static str, usage: wgpu::BufferUsages, size: wgpu::BufferAddress) -> Self {
+        Self { label, usage, size, buffer: create_buffer(device, label, usage, size) }
+    }
+
+    // Returns whether the buffer got replaced.
+    fn reserve(&mut self, device: &wgpu::Device, size: wgpu::BufferAddress) -> bool {
+        if size <= self.size {
+            return false;
+        }
+
+        self.size = size.next_power_of_two();
+        self.buffer = create_buffer(device, self.label, self.usage, self.size);
+
+        true
+    }
+}
+
+fn create_buffer(device: &wgpu::Device, label: &str, usage: wgpu::BufferUsages, size: wgpu::BufferAddress) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_frame_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    frustum_buffer: &wgpu::Buffer,
+    args_buffer: &wgpu::Buffer,
+    instance_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: frustum_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: args_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: instance_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("cull_frame_bind_group"),
+    })
+}
+
+fn buffer_layout_entry(binding: u32, ty: wgpu::BufferBindingType) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::{Quaternion, Vector3};
+use log::warn;
+
+use crate::texture::Texture;
+use crate::material::{AlphaMode, Material, MaterialHandle, MaterialLayouts, PbrProperties, TextureSource, TextureSources};
+use crate::vertex::{ModelVertex, PureVertex, TangentVertex};
+use crate::ecs::object::Entity;
+use crate::ecs::scene::Scene;
+use crate::ecs::component::mesh::MeshComponent;
+use crate::ecs::component::instance::InstanceComponent;
+use crate::ecs::component::transform::TransformComponent;
+
+// Imports the default scene of a glTF file (or its first scene) into `scene'. Every node
+// becomes an object with a transform, keeping the node hierarchy. Each primitive of a
+// node's mesh becomes its own `MeshComponent' on that object, and every glTF material
+// is added to the scene as a PBR `Material'. Returns the root objects.
+pub fn import_gltf<P: AsRef<Path>>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    material_layouts: &MaterialLayouts,
+    path: P,
+    scene: &mut Scene,
+) -> Result<Vec<Entity>> {
+    let path = path.as_ref();
+    let (document, buffers, images) = gltf::import(path)
+        .with_context(|| format!("Failed to load glTF file {}", path.display()))?;
+
+    // Colour maps are sRGB, data maps (metallic/roughness, normals, occlusion) linear. An
+    // image used as both gets uploaded once per colour space.
+    let mut textures: HashMap<(usize, bool), Rc<Texture>> = HashMap::new();
+    let mut texture = |texture: gltf::Texture, srgb: bool| -> Result<Rc<Texture>> {
+        let index = texture.source().index();
+        if let Some(texture) = textures.get(&(index, srgb)) {
+            return Ok(texture.clone());
+        }
+
+        let data = images.get(index)
+            .ok_or_else(|| anyhow!("Material references missing image {}!", index))?;
+        let image = to_dynamic_image(data)
+            .with_context(|| format!("Unsupported image {} in {}", index, path.display()))?;
+        let label = format!("{} (image {})", path.display(), index);
+        let format = if srgb { Texture::SRGB_FORMAT } else { Texture::LINEAR_FORMAT };
+
+        let texture = Rc::new(Texture::from_image_with_format(device, queue, &image, Some(&label), format)?);
+        textures.insert((index, srgb), texture.clone());
+
+        Ok(texture)
+    };
+
+    let white = Rc::new(Texture::from_color(device, queue, [255, 255, 255, 255], "white"));
+    let materials = document.materials().map(|material| {
+        let pbr = material.pbr_metallic_roughness();
+        let mut sources = TextureSources::default();
+        let base_color = match pbr.base_color_texture() {
+            Some(info) => {
+                sources.base_color = texture_source(&info.texture(), path);
+                texture(info.texture(), true)?
+            },
+            None => white.clone(),
+        };
+
+        let mut properties = PbrProperties::from_factors(device, queue, pbr.metallic_factor(), pbr.roughness_factor());
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            sources.metallic_roughness = texture_source(&info.texture(), path);
+            properties.metallic_roughness = texture(info.texture(), false)?;
+        }
+        if let Some(normal) = material.normal_texture() {
+            sources.normal = texture_source(&normal.texture(), path);
+            properties.normal = texture(normal.texture(), false)?;
+            properties.normal_scale = normal.scale();
+        }
+        if let Some(occlusion) = material.occlusion_texture() {
+            sources.occlusion = texture_source(&occlusion.texture(), path);
+            properties.occlusion = texture(occlusion.texture(), false)?;
+            properties.occlusion_strength = occlusion.strength();
+        }
+        if let Some(info) = material.emissive_texture() {
+            sources.emissive = texture_source(&info.texture(), path);
+            properties.emissive = texture(info.texture(), true)?;
+        }
+        properties.emissive_factor = material.emissive_factor();
+
+        let name = material.name().unwrap_or("glTF material");
+
+        let mut pbr_material = Material::new_pbr(
+            device, &material_layouts.pbr, name, base_color, pbr.base_color_factor(), properties,
+        );
+        // Masked materials are drawn opaque, there's no alpha cutoff.
+        if material.alpha_mode() == gltf::material::AlphaMode::Blend {
+            pbr_material.alpha_mode = AlphaMode::Blend;
+        }
+        pbr_material.sources = sources;
+
+        Ok(scene.add_material(pbr_material))
+    }).collect::<Result<Vec<MaterialHandle>>>()?;
+
+    let gltf_scene = document.default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("{} doesn't contain any scenes!", path.display()))?;
+
+    let mut importer = Importer { device, buffers: &buffers, materials: &materials, scene };
+
+    gltf_scene.nodes().map(|node| importer.import_node(&node, None)).collect()
+}
+
+struct Importer<'a> {
+    device: &'a wgpu::Device,
+    buffers: &'a [gltf::buffer::Data],
+    materials: &'a [MaterialHandle],
+    scene: &'a mut Scene,
+}
+
+impl Importer<'_> {
+    fn import_node(&mut self, node: &gltf::Node, parent: Option<Entity>) -> Result<Entity> {
+        let entity = match parent {
+            Some(parent) => self.scene.spawn_child(parent)
+                .ok_or_else(|| anyhow!("Parent of node {} vanished during import!", node.index()))?,
+            None => self.scene.spawn(),
+        };
+
+        let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+        self.scene.add_component(entity, TransformComponent::new(
+            Vector3::from(translation), Quaternion::new(w, x, y, z), Vector3::from(scale),
+        ));
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    warn!("Skipping primitive {} of mesh {}, only triangle lists are supported.",
+                        primitive.index(), mesh.index());
+
+                    continue;
+                }
+
+                let desc = format!("{} #{}", mesh.name().unwrap_or("glTF mesh"), primitive.index());
+                let mut mesh_component = self.import_primitive(&primitive, desc)?;
+                // Primitives without a material keep the renderer's default one.
+                mesh_component.material = primitive.material().index()
+                    .and_then(|index| self.materials.get(index).copied());
+
+                self.scene.add_component(entity, mesh_component);
+            }
+
+            self.scene.add_component(entity, InstanceComponent::single(self.device));
+        }
+
+        for child in node.children() {
+            self.import_node(&child, Some(entity))?;
+        }
+
+        Ok(entity)
+    }
+
+    fn import_primitive(&self, primitive: &gltf::Primitive, desc: String) -> Result<MeshComponent> {
+        let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data.0[..]));
+
+        let positions = reader.read_positions()
+            .ok_or_else(|| anyhow!("Primitive {} has no positions!", desc))?
+            .collect::<Vec<[f32; 3]>>();
+
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let normals = match reader.read_normals() {
+            Some(normals) => normals,
+            None => {
+                // Without normals there's nothing to light, so fall back to flat colours.
+                let [r, g, b, _] = primitive.material().pbr_metallic_roughness().base_color_factor();
+                let colors = match reader.read_colors(0) {
+                    Some(colors) => colors.into_rgb_f32().map(|[cr, cg, cb]| [cr * r, cg * g, cb * b]).collect(),
+                    None => vec![[r, g, b]; positions.len()],
+                };
+
+                let vertices = positions.into_iter().zip(colors)
+                    .map(|(position, color)| PureVertex { position, color })
+                    .collect::<Vec<_>>();
+
+                return Ok(MeshComponent::new(desc, self.device, vertices, indices));
+            },
+        };
+
+        let tex_coords = match reader.read_tex_coords(0) {
+            Some(tex_coords) => tex_coords.into_f32().collect(),
+            None => vec![[0.0, 0.0]; positions.len()],
+        };
+
+        let vertices = positions.into_iter().zip(normals).zip(tex_coords)
+            .map(|((position, normal), tex_coords)| ModelVertex { position, normal, tex_coords });
+
+        match reader.read_tangents() {
+            Some(tangents) => {
+                let vertices = vertices.zip(tangents)
+                    .map(|(v, tangent)| TangentVertex {
+                        position: v.position,
+                        normal: v.normal,
+                        tex_coords: v.tex_coords,
+                        tangent,
+                    })
+                    .collect::<Vec<_>>();
+
+                Ok(MeshComponent::new(desc, self.device, vertices, indices))
+            },
+            None => Ok(MeshComponent::new(desc, self.device, vertices.collect::<Vec<_>>(), indices)),
+        }
+    }
+}
+
+fn to_dynamic_image(data: &gltf::image::Data) -> Result<image::DynamicImage> {
+    use gltf::image::Format;
+
+    let (width, height, pixels) = (data.width, data.height, data.pixels.clone());
+    let image = match data.format {
+        Format::R8 => image::GrayImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageLuma8),
+        Format::R8G8 => image::GrayAlphaImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => image::RgbImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => image::RgbaImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgba8),
+        format => bail!("Pixel format {:?} isn't supported!", format),
+    };
+
+    image.ok_or_else(|| anyhow!("Image data doesn't match its {}x{} size!", width, height))
+}
+
+// Images next to the glTF file can be loaded again on their own, the rest (buffer views
+// and data URIs) only through the file.
+fn texture_source(texture: &gltf::Texture, path: &Path) -> TextureSource {
+    match texture.source().source() {
+        gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            TextureSource::File(directory.join(uri))
+        },
+        _ => TextureSource::Embedded,
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use anyhow::{anyhow, Context, Result};
+
+use crate::texture::Texture;
+use crate::material::{Material, TextureSource};
+use crate::vertex::ModelVertex;
+use crate::ecs::component::mesh::MeshComponent;
+
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: [f32; 3],
+    // Resolved relative to the OBJ file.
+    pub diffuse_texture: Option<PathBuf>,
+}
+
+impl ObjMaterial {
+    // The diffuse colour becomes the tint, so untextured materials keep their colour.
+    pub fn to_material(&self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) -> Result<Material> {
+        let [r, g, b] = self.diffuse;
+
+        let texture = match &self.diffuse_texture {
+            Some(path) => {
+                let bytes = std::fs::read(path)
+                    .with_context(|| format!("Failed to read texture {}", path.display()))?;
+                Texture::from_bytes(device, queue, &bytes, &path.display().to_string())?
+            },
+            None => Texture::from_color(device, queue, [255, 255, 255, 255], "white"),
+        };
+
+        let mut material = Material::new(device, layout, &self.name, Rc::new(texture), [r, g, b, 1.0]);
+        if let Some(path) = &self.diffuse_texture {
+            material.sources.base_color = TextureSource::File(path.clone());
+        }
+
+        Ok(material)
+    }
+}
+
+// One object/group of an OBJ file, with every attribute sharing `indices'. Normals are
+// generated from the faces when the file doesn't provide them, missing UVs are zeroed.
+pub struct ObjModel {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    pub material: Option<ObjMaterial>,
+}
+
+impl ObjModel {
+    pub fn to_mesh_component(&self, device: &wgpu::Device) -> MeshComponent {
+        let vertices = self.positions.iter().zip(self.normals.iter()).zip(self.tex_coords.iter())
+            .map(|((position, normal), tex_coords)| ModelVertex {
+                position: *position,
+                normal: *normal,
+                tex_coords: *tex_coords,
+            })
+            .collect::<Vec<_>>();
+
+        MeshComponent::new(self.name.clone(), device, vertices, self.indices.clone())
+    }
+}
+
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Vec<ObjModel>> {
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let (models, materials) = tobj::load_obj(path, &tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    }).with_context(|| format!("Failed to load OBJ file {}", path.display()))?;
+
+    let materials = materials.with_context(|| format!("Failed to load materials for {}", path.display()))?;
+
+    models.into_iter().map(|model| {
+        let mesh = model.mesh;
+
+        let positions = mesh.positions.chunks_exact(3)
+            .map(|p| [p[0], p[1], p[2]])
+            .collect::<Vec<_>>();
+
+        let normals = if mesh.normals.len() == mesh.positions.len() {
+            mesh.normals.chunks_exact(3).map(|n| [n[0], n[1], n[2]]).collect()
+        } else {
+            generate_normals(&positions, &mesh.indices)
+        };
+
+        let tex_coords = if mesh.texcoords.len() / 2 == positions.len() {
+            // OBJ puts the origin in the bottom left, textures are sampled from the top left.
+            mesh.texcoords.chunks_exact(2).map(|t| [t[0], 1.0 - t[1]]).collect()
+        } else {
+            vec![[0.0, 0.0]; positions.len()]
+        };
+
+        let material = match mesh.material_id {
+            Some(id) => {
+                let material = materials.get(id)
+                    .ok_or_else(|| anyhow!("Model {} references missing material {}!", model.name, id))?;
+
+                Some(ObjMaterial {
+                    name: material.name.clone(),
+                    diffuse: material.diffuse,
+                    diffuse_texture: (!material.diffuse_texture.is_empty())
+                        .then(|| base_dir.join(&material.diffuse_texture)),
+                })
+            },
+            None => None,
+        };
+
+        Ok(ObjModel {
+            name: model.name,
+            positions,
+            normals,
+            tex_coords,
+            indices: mesh.indices,
+            material,
+        })
+    }).collect()
+}
+
+// Convenience for when only the geometry is needed.
+pub fn load_obj_meshes<P: AsRef<Path>>(device: &wgpu::Device, path: P) -> Result<Vec<MeshComponent>> {
+    Ok(load_obj(path)?.iter().map(|model| model.to_mesh_component(device)).collect())
+}
+
+// Area weighted vertex normals, from the face normals of every triangle touching a vertex.
+pub fn generate_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    use cgmath::prelude::*;
+    use cgmath::Vector3;
+
+    let mut normals = vec![Vector3::zero(); positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let (pa, pb, pc) = (Vector3::from(positions[a]), Vector3::from(positions[b]), Vector3::from(positions[c]));
+        let face_normal = (pb - pa).cross(pc - pa);
+
+        normals[a] += face_normal;
+        normals[b] += face_normal;
+        normals[c] += face_normal;
+    }
+
+    normals.into_iter().map(|n| {
+        if n.magnitude2() > 0.0 {
+            n.normalize().into()
+        } else {
+            [0.0, 1.0, 0.0]
+        }
+    }).collect()
+}
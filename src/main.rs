@@ -5,8 +5,18 @@ pub mod state;
 pub mod shader;
 pub mod vertex;
 pub mod camera;
+pub mod culling;
+pub mod indirect;
 pub mod texture;
+pub mod material;
+pub mod shadow;
+pub mod render_graph;
+pub mod post_process;
+pub mod pipeline_cache;
+pub mod skybox;
 pub mod ecs;
+pub mod loader;
+pub mod primitives;
 
 use std::time::Instant;
 use log::LevelFilter;
@@ -0,0 +1,388 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+use crate::ecs::arena::Handle;
+
+pub type MaterialHandle = Handle<Material>;
+
+// Picks the pipeline (and group 1 layout) a material is drawn with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ShadingModel {
+    BlinnPhong,
+    // glTF's metallic-roughness model.
+    Pbr,
+}
+
+impl ShadingModel {
+    pub const ALL: [ShadingModel; 2] = [ShadingModel::BlinnPhong, ShadingModel::Pbr];
+}
+
+// How a material's alpha is used. Blended materials are drawn after everything opaque,
+// back to front, without writing depth.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    Blend,
+}
+
+// Where one of a material's textures came from, so saved scenes can load it again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextureSource {
+    // The slot's neutral default (white, or a flat normal).
+    #[default]
+    None,
+    File(PathBuf),
+    // Decoded from memory, e.g. an image embedded in a glTF file. Can't be saved.
+    Embedded,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextureSources {
+    #[serde(default)]
+    pub base_color: TextureSource,
+    #[serde(default)]
+    pub metallic_roughness: TextureSource,
+    #[serde(default)]
+    pub normal: TextureSource,
+    #[serde(default)]
+    pub occlusion: TextureSource,
+    #[serde(default)]
+    pub emissive: TextureSource,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    tint: [f32; 4],
+}
+
+// The tint comes first, same as in `MaterialUniform', so `Material::set_tint()' works for
+// either model.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PbrMaterialUniform {
+    base_color_factor: [f32; 4],
+    emissive_factor: [f32; 4],
+    // Metallic, roughness, normal scale and occlusion strength.
+    params: [f32; 4],
+}
+
+impl PbrMaterialUniform {
+    fn new(base_color_factor: [f32; 4], pbr: &PbrProperties) -> Self {
+        let [er, eg, eb] = pbr.emissive_factor;
+
+        Self {
+            base_color_factor,
+            emissive_factor: [er, eg, eb, 0.0],
+            params: [pbr.metallic_factor, pbr.roughness_factor, pbr.normal_scale, pbr.occlusion_strength],
+        }
+    }
+}
+
+// The extra maps of a PBR material. Each factor is multiplied with its map, as in glTF.
+// Once the material is built they're only read, see the `Material::set_*()' setters.
+pub struct PbrProperties {
+    // Roughness in G, metalness in B.
+    pub metallic_roughness: Rc<Texture>,
+    pub normal: Rc<Texture>,
+    // Occlusion in R.
+    pub occlusion: Rc<Texture>,
+    pub emissive: Rc<Texture>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+}
+
+impl PbrProperties {
+    // Neutral maps, leaving everything up to the factors.
+    pub fn from_factors(device: &wgpu::Device, queue: &wgpu::Queue, metallic_factor: f32, roughness_factor: f32) -> Self {
+        let white = Rc::new(Texture::from_color_with_format(device, queue, [255, 255, 255, 255], "white (linear)", Texture::LINEAR_FORMAT));
+        let flat_normal = Rc::new(Texture::from_color_with_format(device, queue, [128, 128, 255, 255], "flat normal", Texture::LINEAR_FORMAT));
+        let emissive = Rc::new(Texture::from_color(device, queue, [255, 255, 255, 255], "white"));
+
+        Self {
+            metallic_roughness: white.clone(),
+            normal: flat_normal,
+            occlusion: white,
+            emissive,
+            metallic_factor,
+            roughness_factor,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0; 3],
+        }
+    }
+}
+
+// Group 1 layouts for every shading model.
+pub struct MaterialLayouts {
+    pub blinn_phong: wgpu::BindGroupLayout,
+    pub pbr: wgpu::BindGroupLayout,
+}
+
+impl MaterialLayouts {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            blinn_phong: Material::create_bind_group_layout(device),
+            pbr: Material::create_pbr_bind_group_layout(device),
+        }
+    }
+
+    pub fn get(&self, shading: ShadingModel) -> &wgpu::BindGroupLayout {
+        match shading {
+            ShadingModel::BlinnPhong => &self.blinn_phong,
+            ShadingModel::Pbr => &self.pbr,
+        }
+    }
+}
+
+// Base colour texture multiplied by a tint, bound at group 1 of the lit pipelines. PBR
+// materials additionally carry `pbr'.
+pub struct Material {
+    pub name: String,
+    pub shading: ShadingModel,
+    pub alpha_mode: AlphaMode,
+    pub base_color: Rc<Texture>,
+    pub tint: [f32; 4],
+    // Private, since the factors live in `uniform_buffer' as well. See `pbr()'.
+    pbr: Option<PbrProperties>,
+    // Kept up to date by whoever sets the textures, only used for saving.
+    pub sources: TextureSources,
+    pub uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Material {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_layout_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform_layout_entry(2),
+            ],
+            label: Some("material_bind_group_layout"),
+        })
+    }
+
+    // Base colour, metallic/roughness, normal, occlusion and emissive maps, all sampled
+    // with the base colour's sampler, followed by the factors.
+    pub fn create_pbr_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_layout_entry(0),
+                texture_layout_entry(1),
+                texture_layout_entry(2),
+                texture_layout_entry(3),
+                texture_layout_entry(4),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform_layout_entry(6),
+            ],
+            label: Some("pbr_material_bind_group_layout"),
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        base_color: Rc<Texture>,
+        tint: [f32; 4],
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(format!("Material Buffer ({})", name).as_str()),
+                contents: bytemuck::cast_slice(&[MaterialUniform { tint }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&base_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&base_color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(format!("material_bind_group ({})", name).as_str()),
+        });
+
+        Self {
+            name: name.to_owned(),
+            shading: ShadingModel::BlinnPhong,
+            alpha_mode: AlphaMode::Opaque,
+            base_color,
+            tint,
+            pbr: None,
+            sources: TextureSources::default(),
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // `layout' has to be `MaterialLayouts::pbr'.
+    pub fn new_pbr(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        base_color: Rc<Texture>,
+        base_color_factor: [f32; 4],
+        pbr: PbrProperties,
+    ) -> Self {
+        let uniform = PbrMaterialUniform::new(base_color_factor, &pbr);
+
+        let uniform_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(format!("PBR Material Buffer ({})", name).as_str()),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&base_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&pbr.metallic_roughness.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&pbr.normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&pbr.occlusion.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&pbr.emissive.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&base_color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(format!("pbr_material_bind_group ({})", name).as_str()),
+        });
+
+        Self {
+            name: name.to_owned(),
+            shading: ShadingModel::Pbr,
+            alpha_mode: AlphaMode::Opaque,
+            base_color,
+            tint: base_color_factor,
+            pbr: Some(pbr),
+            sources: TextureSources::default(),
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // A plain colour, backed by a 1x1 white texture.
+    pub fn from_tint(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        tint: [f32; 4],
+    ) -> Self {
+        let white = Texture::from_color(device, queue, [255, 255, 255, 255], "white");
+
+        Self::new(device, layout, name, Rc::new(white), tint)
+    }
+
+    pub fn pbr(&self) -> Option<&PbrProperties> {
+        self.pbr.as_ref()
+    }
+
+    pub fn set_tint(&mut self, queue: &wgpu::Queue, tint: [f32; 4]) {
+        self.tint = tint;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[MaterialUniform { tint }]));
+    }
+
+    // The PBR setters do nothing for other shading models.
+    pub fn set_metallic_roughness(&mut self, queue: &wgpu::Queue, metallic_factor: f32, roughness_factor: f32) {
+        self.update_pbr(queue, |pbr| {
+            pbr.metallic_factor = metallic_factor;
+            pbr.roughness_factor = roughness_factor;
+        });
+    }
+
+    pub fn set_normal_scale(&mut self, queue: &wgpu::Queue, normal_scale: f32) {
+        self.update_pbr(queue, |pbr| pbr.normal_scale = normal_scale);
+    }
+
+    pub fn set_occlusion_strength(&mut self, queue: &wgpu::Queue, occlusion_strength: f32) {
+        self.update_pbr(queue, |pbr| pbr.occlusion_strength = occlusion_strength);
+    }
+
+    pub fn set_emissive_factor(&mut self, queue: &wgpu::Queue, emissive_factor: [f32; 3]) {
+        self.update_pbr(queue, |pbr| pbr.emissive_factor = emissive_factor);
+    }
+
+    fn update_pbr(&mut self, queue: &wgpu::Queue, update: impl FnOnce(&mut PbrProperties)) {
+        if let Some(pbr) = &mut self.pbr {
+            update(pbr);
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[PbrMaterialUniform::new(self.tint, pbr)]));
+        }
+    }
+}
+
+fn texture_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn uniform_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use anyhow::{anyhow, Result};
+
+// Owned version of `wgpu::VertexBufferLayout', so it can be part of a key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexBufferKey {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl From<&wgpu::VertexBufferLayout<'_>> for VertexBufferKey {
+    fn from(layout: &wgpu::VertexBufferLayout) -> Self {
+        Self {
+            array_stride: layout.array_stride,
+            step_mode: layout.step_mode,
+            attributes: layout.attributes.to_vec(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ColorTargetKey {
+    pub format: wgpu::TextureFormat,
+    pub blend: Option<wgpu::BlendState>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DepthKey {
+    pub format: wgpu::TextureFormat,
+    pub write_enabled: bool,
+    pub compare: wgpu::CompareFunction,
+    pub bias: DepthBiasKey,
+}
+
+// `wgpu::DepthBiasState' with its floats stored as bits, so it can be part of a key.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DepthBiasKey {
+    pub constant: i32,
+    slope_scale: u32,
+    clamp: u32,
+}
+
+impl From<wgpu::DepthBiasState> for DepthBiasKey {
+    fn from(bias: wgpu::DepthBiasState) -> Self {
+        Self {
+            constant: bias.constant,
+            slope_scale: bias.slope_scale.to_bits(),
+            clamp: bias.clamp.to_bits(),
+        }
+    }
+}
+
+impl From<DepthBiasKey> for wgpu::DepthBiasState {
+    fn from(bias: DepthBiasKey) -> Self {
+        Self {
+            constant: bias.constant,
+            slope_scale: f32::from_bits(bias.slope_scale),
+            clamp: f32::from_bits(bias.clamp),
+        }
+    }
+}
+
+// Everything a render pipeline is built from. Shaders and pipeline layouts are referred
+// to by the names they were registered under.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub vertex_shader: &'static str,
+    pub fragment_shader: Option<&'static str>,
+    pub layout: &'static str,
+    pub vertex_buffers: Vec<VertexBufferKey>,
+    pub color_targets: Vec<ColorTargetKey>,
+    pub depth: Option<DepthKey>,
+    pub cull_mode: Option<wgpu::Face>,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub sample_count: u32,
+}
+
+// Builds render pipelines the first time their key is asked for and hands out the same
+// pipeline afterwards. Lookups only need `&self', so passes can fetch pipelines while
+// recording; hold on to the returned `Rc' for as long as the render pass uses it.
+#[derive(Default)]
+pub struct PipelineCache {
+    shaders: HashMap<&'static str, wgpu::ShaderModule>,
+    layouts: HashMap<&'static str, wgpu::PipelineLayout>,
+    pipelines: RefCell<HashMap<PipelineKey, Rc<wgpu::RenderPipeline>>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Shaders' entry points have to be named `main'.
+    pub fn add_shader(&mut self, name: &'static str, module: wgpu::ShaderModule) {
+        self.shaders.insert(name, module);
+        self.forget(|key| key.vertex_shader == name || key.fragment_shader == Some(name));
+    }
+
+    pub fn add_layout(&mut self, name: &'static str, layout: wgpu::PipelineLayout) {
+        self.layouts.insert(name, layout);
+        self.forget(|key| key.layout == name);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.pipelines.borrow_mut().clear();
+    }
+
+    pub fn get(&self, device: &wgpu::Device, key: &PipelineKey) -> Result<Rc<wgpu::RenderPipeline>> {
+        if let Some(pipeline) = self.pipelines.borrow().get(key) {
+            return Ok(pipeline.clone());
+        }
+
+        let pipeline = Rc::new(self.create_pipeline(device, key)?);
+        self.pipelines.borrow_mut().insert(key.clone(), pipeline.clone());
+
+        Ok(pipeline)
+    }
+
+    fn create_pipeline(&self, device: &wgpu::Device, key: &PipelineKey) -> Result<wgpu::RenderPipeline> {
+        let vertex_shader = self.shader(key.vertex_shader)?;
+        let fragment_shader = key.fragment_shader.map(|name| self.shader(name)).transpose()?;
+        let layout = self.layouts.get(key.layout)
+            .ok_or_else(|| anyhow!("Unknown pipeline layout `{}'!", key.layout))?;
+
+        let vertex_buffers = key.vertex_buffers.iter()
+            .map(|buffer| wgpu::VertexBufferLayout {
+                array_stride: buffer.array_stride,
+                step_mode: buffer.step_mode,
+                attributes: &buffer.attributes,
+            })
+            .collect::<Vec<_>>();
+
+        let color_targets = key.color_targets.iter()
+            .map(|target| Some(wgpu::ColorTargetState {
+                format: target.format,
+                blend: target.blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            }))
+            .collect::<Vec<_>>();
+
+        Ok(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(format!("Render Pipeline ({} + {})", key.vertex_shader, key.fragment_shader.unwrap_or("depth only")).as_str()),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: vertex_shader,
+                entry_point: "main",
+                buffers: &vertex_buffers,
+            },
+            fragment: fragment_shader.map(|module| wgpu::FragmentState {
+                module,
+                entry_point: "main",
+                targets: &color_targets,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: key.cull_mode,
+                polygon_mode: key.polygon_mode,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: key.depth.map(|depth| wgpu::DepthStencilState {
+                format: depth.format,
+                depth_write_enabled: depth.write_enabled,
+                depth_compare: depth.compare,
+                stencil: wgpu::StencilState::default(),
+                bias: depth.bias.into(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: key.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        }))
+    }
+
+    fn shader(&self, name: &str) -> Result<&wgpu::ShaderModule> {
+        self.shaders.get(name).ok_or_else(|| anyhow!("Unknown shader `{}'!", name))
+    }
+
+    // Drops pipelines built from something that just got replaced.
+    fn forget<F: Fn(&PipelineKey) -> bool>(&mut self, uses: F) {
+        self.pipelines.get_mut().retain(|key, _| !uses(key));
+    }
+}
@@ -0,0 +1,419 @@
+use wgpu::util::DeviceExt;
+
+use crate::texture::Texture;
+use crate::pipeline_cache::{ColorTargetKey, PipelineCache, PipelineKey};
+use crate::render_graph::{PassContext, RenderGraph, TextureDesc, TextureSize, SURFACE};
+
+// Intermediate targets the chain ping-pongs between, and the half resolution bloom
+// targets. Declared on the render graph by `PostProcessStack::add_resources()'.
+pub const POST_PING: &str = "post_ping";
+pub const POST_PONG: &str = "post_pong";
+pub const BLOOM_A: &str = "bloom_a";
+pub const BLOOM_B: &str = "bloom_b";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TonemapOperator {
+    Aces,
+    Reinhard,
+}
+
+// Maps HDR colours into [0, 1].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tonemap {
+    pub operator: TonemapOperator,
+    pub exposure: f32,
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Self { operator: TonemapOperator::Aces, exposure: 1.0 }
+    }
+}
+
+// Only needed when presenting to a non-sRGB surface, sRGB surfaces encode on write.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gamma {
+    pub gamma: f32,
+}
+
+impl Default for Gamma {
+    fn default() -> Self {
+        Self { gamma: 2.2 }
+    }
+}
+
+// Blurs everything brighter than `threshold' and adds it back on top. Belongs before
+// tonemapping, where colours can still exceed 1.0.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    pub threshold: f32,
+    pub intensity: f32,
+    // Spacing of the blur taps, in (half resolution) texels.
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self { threshold: 1.0, intensity: 0.5, radius: 1.5 }
+    }
+}
+
+// Belongs after tonemapping, edges are detected from the final luma.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fxaa {
+    // Longest edge searched, in texels.
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self { span_max: 8.0, reduce_mul: 1.0 / 8.0, reduce_min: 1.0 / 128.0 }
+    }
+}
+
+// Darkens the corners. `radius' is where darkening starts (0.5 touches the edges' centres).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vignette {
+    pub intensity: f32,
+    pub radius: f32,
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self { intensity: 0.5, radius: 0.75, softness: 0.45 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PostEffect {
+    Tonemap(Tonemap),
+    Gamma(Gamma),
+    Bloom(Bloom),
+    Fxaa(Fxaa),
+    Vignette(Vignette),
+}
+
+impl PostEffect {
+    fn params(&self) -> [f32; 4] {
+        match self {
+            PostEffect::Tonemap(tonemap) => {
+                let operator = match tonemap.operator {
+                    TonemapOperator::Aces => 0.0,
+                    TonemapOperator::Reinhard => 1.0,
+                };
+
+                [operator, tonemap.exposure, 0.0, 0.0]
+            },
+            PostEffect::Gamma(gamma) => [gamma.gamma, 0.0, 0.0, 0.0],
+            PostEffect::Bloom(bloom) => [bloom.threshold, bloom.intensity, bloom.radius, 0.0],
+            PostEffect::Fxaa(fxaa) => [fxaa.span_max, fxaa.reduce_mul, fxaa.reduce_min, 0.0],
+            PostEffect::Vignette(vignette) => [vignette.intensity, vignette.radius, vignette.softness, 0.0],
+        }
+    }
+
+    // Fullscreen draws making up the effect.
+    fn steps(&self) -> Vec<EffectStep> {
+        match self {
+            PostEffect::Tonemap(_) => vec![EffectStep::simple(EffectShader::Tonemap)],
+            PostEffect::Gamma(_) => vec![EffectStep::simple(EffectShader::Gamma)],
+            PostEffect::Fxaa(_) => vec![EffectStep::simple(EffectShader::Fxaa)],
+            PostEffect::Vignette(_) => vec![EffectStep::simple(EffectShader::Vignette)],
+            PostEffect::Bloom(_) => vec![
+                EffectStep { output: Some(BLOOM_A), ..EffectStep::simple(EffectShader::BloomExtract) },
+                EffectStep { input: Some(BLOOM_A), secondary: None, output: Some(BLOOM_B), shader: EffectShader::BloomBlurX },
+                EffectStep { input: Some(BLOOM_B), secondary: None, output: Some(BLOOM_A), shader: EffectShader::BloomBlurY },
+                EffectStep { secondary: Some(BLOOM_A), ..EffectStep::simple(EffectShader::BloomComposite) },
+            ],
+        }
+    }
+}
+
+// `None' stands for the effect's own input and output. The secondary input defaults to
+// the step's input.
+struct EffectStep {
+    shader: EffectShader,
+    input: Option<&'static str>,
+    secondary: Option<&'static str>,
+    output: Option<&'static str>,
+}
+
+impl EffectStep {
+    fn simple(shader: EffectShader) -> Self {
+        Self { shader, input: None, secondary: None, output: None }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum EffectShader {
+    Blit,
+    Tonemap,
+    Gamma,
+    BloomExtract,
+    BloomBlurX,
+    BloomBlurY,
+    BloomComposite,
+    Fxaa,
+    Vignette,
+}
+
+impl EffectShader {
+    // What the shader is registered as with the `PipelineCache'.
+    fn name(self) -> &'static str {
+        match self {
+            EffectShader::Blit => "blit",
+            EffectShader::Tonemap => "tonemap",
+            EffectShader::Gamma => "gamma",
+            EffectShader::BloomExtract => "bloom_extract",
+            EffectShader::BloomBlurX => "bloom_blur_x",
+            EffectShader::BloomBlurY => "bloom_blur_y",
+            EffectShader::BloomComposite => "bloom_composite",
+            EffectShader::Fxaa => "fxaa",
+            EffectShader::Vignette => "vignette",
+        }
+    }
+
+    // Any effect may end up writing the surface, or an intermediate target.
+    fn pipeline_key(self, format: wgpu::TextureFormat) -> PipelineKey {
+        PipelineKey {
+            vertex_shader: "fullscreen_vertex",
+            fragment_shader: Some(self.name()),
+            layout: "post_process",
+            vertex_buffers: vec![],
+            color_targets: vec![ColorTargetKey {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+            }],
+            depth: None,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            sample_count: 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EffectUniform {
+    params: [f32; 4],
+}
+
+// Chain of fullscreen effects taking the HDR scene to the surface, run in order. With no
+// effects the scene is copied as is.
+pub struct PostProcessStack {
+    pub effects: Vec<PostEffect>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    // One per effect, see `update()'.
+    uniform_buffers: Vec<wgpu::Buffer>,
+    blit_buffer: wgpu::Buffer,
+}
+
+impl PostProcessStack {
+    // Registers the effects' shaders and layout with `pipeline_cache'.
+    pub fn new(device: &wgpu::Device, pipeline_cache: &mut PipelineCache, effects: Vec<PostEffect>) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_layout_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_layout_entry(3),
+            ],
+            label: Some("post_process_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let vertex_shader = create_spv_shader!(device, "../target/fullscreen_vertex.spv", "fullscreen_vertex");
+        let shaders = [
+            (EffectShader::Blit, create_spv_shader!(device, "../target/blit.spv", "blit")),
+            (EffectShader::Tonemap, create_spv_shader!(device, "../target/tonemap.spv", "tonemap")),
+            (EffectShader::Gamma, create_spv_shader!(device, "../target/gamma.spv", "gamma")),
+            (EffectShader::BloomExtract, create_spv_shader!(device, "../target/bloom_extract.spv", "bloom_extract")),
+            (EffectShader::BloomBlurX, create_spv_shader!(device, "../target/bloom_blur_x.spv", "bloom_blur_x")),
+            (EffectShader::BloomBlurY, create_spv_shader!(device, "../target/bloom_blur_y.spv", "bloom_blur_y")),
+            (EffectShader::BloomComposite, create_spv_shader!(device, "../target/bloom_composite.spv", "bloom_composite")),
+            (EffectShader::Fxaa, create_spv_shader!(device, "../target/fxaa.spv", "fxaa")),
+            (EffectShader::Vignette, create_spv_shader!(device, "../target/vignette.spv", "vignette")),
+        ];
+
+        pipeline_cache.add_shader("fullscreen_vertex", vertex_shader);
+        for (shader, module) in shaders {
+            pipeline_cache.add_shader(shader.name(), module);
+        }
+        pipeline_cache.add_layout("post_process", pipeline_layout);
+
+        let blit_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Post Process Buffer (blit)"),
+                contents: bytemuck::cast_slice(&[EffectUniform { params: [0.0; 4] }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            }
+        );
+
+        Self {
+            effects,
+            bind_group_layout,
+            uniform_buffers: vec![],
+            blit_buffer,
+        }
+    }
+
+    // Tonemapping, plus gamma correction when the surface doesn't do it by itself.
+    pub fn default_effects(surface_format: wgpu::TextureFormat) -> Vec<PostEffect> {
+        let mut effects = vec![PostEffect::Tonemap(Tonemap::default())];
+        if !surface_format.describe().srgb {
+            effects.push(PostEffect::Gamma(Gamma::default()));
+        }
+
+        effects
+    }
+
+    pub fn add_resources(graph: &mut RenderGraph, device: &wgpu::Device) {
+        graph.add_texture(device, POST_PING, TextureDesc::attachment(Texture::HDR_FORMAT));
+        graph.add_texture(device, POST_PONG, TextureDesc::attachment(Texture::HDR_FORMAT));
+
+        for name in [BLOOM_A, BLOOM_B] {
+            graph.add_texture(device, name, TextureDesc {
+                size: TextureSize::Surface { scale: 0.5 },
+                ..TextureDesc::attachment(Texture::HDR_FORMAT)
+            });
+        }
+    }
+
+    // Uploads the effects' parameters. Effects added after the last update are skipped
+    // until the next one.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        while self.uniform_buffers.len() < self.effects.len() {
+            self.uniform_buffers.push(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(format!("Post Process Buffer ({})", self.uniform_buffers.len()).as_str()),
+                size: std::mem::size_of::<EffectUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+
+        for (effect, buffer) in self.effects.iter().zip(self.uniform_buffers.iter()) {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[EffectUniform { params: effect.params() }]));
+        }
+    }
+
+    // Runs the chain from `input' to the surface.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext, input: &str) {
+        let mut steps = vec![];
+        let mut current = input;
+        let count = self.effects.len().min(self.uniform_buffers.len());
+
+        for (index, (effect, buffer)) in self.effects.iter().zip(self.uniform_buffers.iter()).enumerate() {
+            let output = if index + 1 == count {
+                SURFACE
+            } else if current == POST_PING {
+                POST_PONG
+            } else {
+                POST_PING
+            };
+
+            for step in effect.steps() {
+                let step_input = step.input.unwrap_or(current);
+                steps.push((step.shader, step_input, step.secondary.unwrap_or(step_input), step.output.unwrap_or(output), buffer));
+            }
+
+            current = output;
+        }
+
+        if steps.is_empty() {
+            steps.push((EffectShader::Blit, input, input, SURFACE, &self.blit_buffer));
+        }
+
+        for (shader, step_input, secondary, output, buffer) in steps {
+            let format = if output == SURFACE { context.surface_format() } else { Texture::HDR_FORMAT };
+            let (pipeline, input_texture, secondary_view, output_view) = match (
+                context.state.pipeline_cache.get(context.device, &shader.pipeline_key(format)),
+                context.texture(step_input),
+                context.view(secondary),
+                context.view(output),
+            ) {
+                (Ok(pipeline), Some(input_texture), Some(secondary_view), Some(output_view)) => {
+                    (pipeline, input_texture, secondary_view, output_view)
+                },
+                _ => continue,
+            };
+
+            let bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&input_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&input_texture.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(secondary_view),
+                    },
+                ],
+                label: Some("post_process_bind_group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Process Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: output_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        }
+                    })
+                ],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn texture_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+use cgmath::prelude::*;
+use cgmath::{Vector2, Vector3};
+
+use crate::vertex::ModelVertex;
+use crate::ecs::component::mesh::MeshComponent;
+
+// Generated shapes, centred on the origin with +y up. Like the loaders' meshes, triangles are
+// counter-clockwise seen from outside and texture coordinates start in the top left.
+
+// Every face gets the whole texture.
+pub fn cube(device: &wgpu::Device, size: f32) -> MeshComponent {
+    cube_geometry(size).into_mesh_component(device, "Cube")
+}
+
+fn cube_geometry(size: f32) -> Geometry {
+    let half = size * 0.5;
+    let mut geometry = Geometry::default();
+
+    // Normal, then the directions of the face's right and up edges.
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_z(), Vector3::unit_y()),
+        (-Vector3::unit_x(), Vector3::unit_z(), Vector3::unit_y()),
+        (Vector3::unit_y(), Vector3::unit_x(), -Vector3::unit_z()),
+        (-Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_z(), Vector3::unit_x(), Vector3::unit_y()),
+        (-Vector3::unit_z(), -Vector3::unit_x(), Vector3::unit_y()),
+    ];
+
+    for (normal, right, up) in faces {
+        let first = geometry.vertices.len() as u32;
+        for (v, up_sign) in [(0.0, 1.0), (1.0, -1.0)] {
+            for (u, right_sign) in [(0.0, -1.0), (1.0, 1.0)] {
+                let position = (normal + right * right_sign + up * up_sign) * half;
+                geometry.vertex(position, normal, [u, v]);
+            }
+        }
+
+        geometry.grid(first, 1, 1);
+    }
+
+    geometry
+}
+
+// Lies in the xz plane facing +y, split into `subdivisions' quads along x and z.
+pub fn plane(device: &wgpu::Device, size: [f32; 2], subdivisions: [u32; 2]) -> MeshComponent {
+    plane_geometry(size, subdivisions).into_mesh_component(device, "Plane")
+}
+
+fn plane_geometry(size: [f32; 2], subdivisions: [u32; 2]) -> Geometry {
+    let [columns, rows] = subdivisions.map(|count| count.max(1));
+    let mut geometry = Geometry::default();
+
+    for row in 0..=rows {
+        let v = row as f32 / rows as f32;
+        for column in 0..=columns {
+            let u = column as f32 / columns as f32;
+            let position = Vector3::new((u - 0.5) * size[0], 0.0, (v - 0.5) * size[1]);
+            geometry.vertex(position, Vector3::unit_y(), [u, v]);
+        }
+    }
+
+    geometry.grid(0, columns, rows);
+    geometry
+}
+
+// Latitude/longitude sphere, with `sectors' around and `stacks' from pole to pole.
+pub fn uv_sphere(device: &wgpu::Device, radius: f32, sectors: u32, stacks: u32) -> MeshComponent {
+    uv_sphere_geometry(radius, sectors, stacks).into_mesh_component(device, "UV Sphere")
+}
+
+fn uv_sphere_geometry(radius: f32, sectors: u32, stacks: u32) -> Geometry {
+    let stacks = stacks.max(2);
+    let profile = (0..=stacks).map(|stack| {
+        let angle = PI * stack as f32 / stacks as f32;
+        // Exactly zero at the poles, so the triangles there collapse and get dropped.
+        let normal = Vector2::new(angle.sin().max(0.0), angle.cos());
+        (normal * radius, normal)
+    }).collect::<Vec<_>>();
+
+    let mut geometry = Geometry::default();
+    geometry.revolve(&profile, sectors);
+    geometry
+}
+
+// Icosahedron with every triangle split into four `subdivisions' times, which spreads the
+// vertices far more evenly than `uv_sphere()'.
+pub fn icosphere(device: &wgpu::Device, radius: f32, subdivisions: u32) -> MeshComponent {
+    icosphere_geometry(radius, subdivisions).into_mesh_component(device, "Icosphere")
+}
+
+fn icosphere_geometry(radius: f32, subdivisions: u32) -> Geometry {
+    let t = (1.0 + 5f32.sqrt()) * 0.5;
+    let mut directions = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ].map(|direction| Vector3::from(direction).normalize()).to_vec();
+
+    let mut triangles = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            directions.push((directions[a as usize] + directions[b as usize]).normalize());
+            directions.len() as u32 - 1
+        });
+
+        triangles = triangles.into_iter().flat_map(|[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        }).collect();
+    }
+
+    let mut geometry = Geometry::default();
+    for direction in &directions {
+        geometry.vertex(direction * radius, *direction, spherical_tex_coords(direction));
+    }
+
+    // The poles have no u of their own, they're sorted out after the seam.
+    let poles = directions.iter().map(|direction| direction.x.abs() < 1e-6 && direction.z.abs() < 1e-6).collect::<Vec<_>>();
+    let is_pole = |index: u32| poles.get(index as usize).copied().unwrap_or(false);
+
+    // Triangles across the seam at +z would otherwise stretch over the whole texture.
+    let mut seam_copies = HashMap::new();
+    for triangle in &mut triangles {
+        let us = triangle.iter()
+            .filter(|&&index| !is_pole(index))
+            .map(|&index| geometry.vertices[index as usize].tex_coords[0])
+            .collect::<Vec<_>>();
+        let span = us.iter().copied().fold(f32::MIN, f32::max) - us.iter().copied().fold(f32::MAX, f32::min);
+        if span <= 0.5 {
+            continue;
+        }
+
+        for index in triangle.iter_mut() {
+            if is_pole(*index) || geometry.vertices[*index as usize].tex_coords[0] >= 0.5 {
+                continue;
+            }
+
+            *index = *seam_copies.entry(*index).or_insert_with(|| {
+                let mut vertex = geometry.vertices[*index as usize];
+                vertex.tex_coords[0] += 1.0;
+                geometry.vertices.push(vertex);
+                geometry.vertices.len() as u32 - 1
+            });
+        }
+    }
+
+    // Every triangle gets its own copy of the pole, in the middle of its other corners.
+    for triangle in &mut triangles {
+        if let Some(corner) = (0..3).find(|&corner| is_pole(triangle[corner])) {
+            let others = [triangle[(corner + 1) % 3], triangle[(corner + 2) % 3]]
+                .map(|index| geometry.vertices[index as usize].tex_coords[0]);
+
+            let mut vertex = geometry.vertices[triangle[corner] as usize];
+            vertex.tex_coords[0] = (others[0] + others[1]) * 0.5;
+            geometry.vertices.push(vertex);
+            triangle[corner] = geometry.vertices.len() as u32 - 1;
+        }
+    }
+
+    for [a, b, c] in triangles {
+        geometry.triangle(a, b, c);
+    }
+
+    geometry
+}
+
+// Capped, `height' tall with `segments' sides.
+pub fn cylinder(device: &wgpu::Device, radius: f32, height: f32, segments: u32) -> MeshComponent {
+    cylinder_geometry(radius, height, segments).into_mesh_component(device, "Cylinder")
+}
+
+fn cylinder_geometry(radius: f32, height: f32, segments: u32) -> Geometry {
+    let half = height * 0.5;
+    let normal = Vector2::unit_x();
+
+    let mut geometry = Geometry::default();
+    geometry.revolve(&[(Vector2::new(radius, half), normal), (Vector2::new(radius, -half), normal)], segments);
+    geometry.disc(radius, half, segments, true);
+    geometry.disc(radius, -half, segments, false);
+    geometry
+}
+
+// Point up, `height' tall with a capped base.
+pub fn cone(device: &wgpu::Device, radius: f32, height: f32, segments: u32) -> MeshComponent {
+    cone_geometry(radius, height, segments).into_mesh_component(device, "Cone")
+}
+
+fn cone_geometry(radius: f32, height: f32, segments: u32) -> Geometry {
+    let half = height * 0.5;
+    let normal = Vector2::new(height, radius).normalize();
+
+    let mut geometry = Geometry::default();
+    geometry.revolve(&[(Vector2::new(0.0, half), normal), (Vector2::new(radius, -half), normal)], segments);
+    geometry.disc(radius, -half, segments, false);
+    geometry
+}
+
+// Cylinder with hemispheres for caps, `height' tall including them. `rings' is per hemisphere.
+pub fn capsule(device: &wgpu::Device, radius: f32, height: f32, segments: u32, rings: u32) -> MeshComponent {
+    capsule_geometry(radius, height, segments, rings).into_mesh_component(device, "Capsule")
+}
+
+fn capsule_geometry(radius: f32, height: f32, segments: u32, rings: u32) -> Geometry {
+    let rings = rings.max(1);
+    let half = (height * 0.5 - radius).max(0.0);
+
+    let hemisphere = |start: f32, center: f32| (0..=rings).map(move |ring| {
+        let angle = start + FRAC_PI_2 * ring as f32 / rings as f32;
+        let normal = Vector2::new(angle.sin().max(0.0), angle.cos());
+        (normal * radius + Vector2::new(0.0, center), normal)
+    });
+    let profile = hemisphere(0.0, half).chain(hemisphere(FRAC_PI_2, -half)).collect::<Vec<_>>();
+
+    let mut geometry = Geometry::default();
+    geometry.revolve(&profile, segments);
+    geometry
+}
+
+// Ring around the y axis, `segments' around it and `sides' around its tube.
+pub fn torus(device: &wgpu::Device, major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> MeshComponent {
+    torus_geometry(major_radius, minor_radius, segments, sides).into_mesh_component(device, "Torus")
+}
+
+fn torus_geometry(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> Geometry {
+    let sides = sides.max(3);
+    let profile = (0..=sides).map(|side| {
+        let angle = TAU * side as f32 / sides as f32;
+        let normal = Vector2::new(angle.cos(), -angle.sin());
+        (normal * minor_radius + Vector2::new(major_radius, 0.0), normal)
+    }).collect::<Vec<_>>();
+
+    let mut geometry = Geometry::default();
+    geometry.revolve(&profile, segments);
+    geometry
+}
+
+#[derive(Default)]
+struct Geometry {
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+}
+
+impl Geometry {
+    fn vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tex_coords: [f32; 2]) -> u32 {
+        self.vertices.push(ModelVertex { position: position.into(), normal: normal.into(), tex_coords });
+        self.vertices.len() as u32 - 1
+    }
+
+    // Triangles with corners in the same spot (at poles and apexes) are left out.
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let position = |index: u32| self.vertices[index as usize].position;
+        if position(a) == position(b) || position(b) == position(c) || position(c) == position(a) {
+            return;
+        }
+
+        self.indices.extend([a, b, c]);
+    }
+
+    // `columns' by `rows' quads over the vertices from `first' on, which are laid out row by
+    // row from the top left, left to right.
+    fn grid(&mut self, first: u32, columns: u32, rows: u32) {
+        for row in 0..rows {
+            for column in 0..columns {
+                let top_left = first + row * (columns + 1) + column;
+                let bottom_left = top_left + columns + 1;
+
+                self.triangle(bottom_left, bottom_left + 1, top_left + 1);
+                self.triangle(bottom_left, top_left + 1, top_left);
+            }
+        }
+    }
+
+    // Sweeps `profile' (positions and normals as distance from the y axis and height) around
+    // the y axis, starting and ending at +z. It has to run top to bottom along the outside.
+    // u goes around, v follows the profile by its length.
+    fn revolve(&mut self, profile: &[(Vector2<f32>, Vector2<f32>)], segments: u32) {
+        let segments = segments.max(3);
+        let lengths = profile.iter()
+            .scan(None, |previous: &mut Option<Vector2<f32>>, (position, _)| {
+                let length = previous.map_or(0.0, |previous| (position - previous).magnitude());
+                *previous = Some(*position);
+                Some(length)
+            })
+            .scan(0.0, |total, length| {
+                *total += length;
+                Some(*total)
+            })
+            .collect::<Vec<f32>>();
+        let total_length = lengths.last().copied().unwrap_or(0.0).max(f32::EPSILON);
+
+        let first = self.vertices.len() as u32;
+        for ((position, normal), length) in profile.iter().zip(&lengths) {
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let (sin, cos) = (TAU * u).sin_cos();
+                let radial = Vector3::new(sin, 0.0, cos);
+
+                self.vertex(
+                    radial * position.x + Vector3::unit_y() * position.y,
+                    (radial * normal.x + Vector3::unit_y() * normal.y).normalize(),
+                    [u, length / total_length],
+                );
+            }
+        }
+
+        self.grid(first, segments, profile.len().saturating_sub(1) as u32);
+    }
+
+    // Flat cap at height `y', facing +y or -y. The texture is laid over it as seen from
+    // outside.
+    fn disc(&mut self, radius: f32, y: f32, segments: u32, facing_up: bool) {
+        let segments = segments.max(3);
+        let normal = if facing_up { Vector3::unit_y() } else { -Vector3::unit_y() };
+        let v_sign = if facing_up { 1.0 } else { -1.0 };
+
+        let center = self.vertex(Vector3::new(0.0, y, 0.0), normal, [0.5, 0.5]);
+        for segment in 0..segments {
+            let (sin, cos) = (TAU * segment as f32 / segments as f32).sin_cos();
+            self.vertex(Vector3::new(sin * radius, y, cos * radius), normal, [0.5 + sin * 0.5, 0.5 + cos * 0.5 * v_sign]);
+        }
+
+        for segment in 0..segments {
+            let current = center + 1 + segment;
+            let next = center + 1 + (segment + 1) % segments;
+
+            if facing_up {
+                self.triangle(center, current, next);
+            } else {
+                self.triangle(center, next, current);
+            }
+        }
+    }
+
+    fn into_mesh_component(self, device: &wgpu::Device, desc: &str) -> MeshComponent {
+        MeshComponent::new(desc.to_owned(), device, self.vertices, self.indices)
+    }
+}
+
+// Same mapping as `revolve()' gives a sphere, u going around from +z and v from the top.
+fn spherical_tex_coords(direction: &Vector3<f32>) -> [f32; 2] {
+    let u = direction.x.atan2(direction.z) / TAU;
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+
+    [if u < 0.0 { u + 1.0 } else { u }, v]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shapes() -> Vec<(&'static str, Geometry)> {
+        vec![
+            ("cube", cube_geometry(2.0)),
+            ("plane", plane_geometry([3.0, 1.0], [4, 2])),
+            ("uv sphere", uv_sphere_geometry(1.5, 16, 8)),
+            ("icosphere", icosphere_geometry(1.0, 2)),
+            ("cylinder", cylinder_geometry(0.5, 2.0, 12)),
+            ("cone", cone_geometry(1.0, 2.0, 12)),
+            ("capsule", capsule_geometry(0.5, 3.0, 12, 4)),
+            // No cylinder left between the hemispheres.
+            ("short capsule", capsule_geometry(1.0, 1.0, 12, 4)),
+            ("torus", torus_geometry(2.0, 0.5, 16, 8)),
+        ]
+    }
+
+    #[test]
+    fn indices_stay_in_range() {
+        for (name, geometry) in shapes() {
+            assert!(!geometry.indices.is_empty(), "{} has no triangles", name);
+            assert_eq!(geometry.indices.len() % 3, 0, "{} has a partial triangle", name);
+            assert!(geometry.indices.iter().all(|&index| (index as usize) < geometry.vertices.len()), "{} indexes past its vertices", name);
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_length() {
+        for (name, geometry) in shapes() {
+            for vertex in &geometry.vertices {
+                let length = Vector3::from(vertex.normal).magnitude();
+                assert!((length - 1.0).abs() < 1e-5, "{} has a normal of length {}", name, length);
+            }
+        }
+    }
+
+    // Counter-clockwise from outside means the winding's normal points the same way as the
+    // vertex normals.
+    #[test]
+    fn triangles_wind_counter_clockwise_from_outside() {
+        for (name, geometry) in shapes() {
+            for triangle in geometry.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|corner| geometry.vertices[triangle[corner] as usize]);
+                let position = |vertex: ModelVertex| Vector3::from(vertex.position);
+                let winding = (position(b) - position(a)).cross(position(c) - position(a));
+
+                for vertex in [a, b, c] {
+                    assert!(winding.dot(Vector3::from(vertex.normal)) > 0.0, "{} has a triangle facing inwards: {:?}", name, triangle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_are_centred_on_the_origin() {
+        for (name, geometry) in shapes() {
+            let positions = geometry.vertices.iter().map(|vertex| vertex.position).collect::<Vec<_>>();
+            let bounds = crate::culling::Aabb::from_points(&positions);
+            let center = bounds.min.midpoint(bounds.max);
+
+            assert!(center.to_vec().magnitude() < 1e-5, "{} is centred on {:?}", name, center);
+        }
+    }
+}
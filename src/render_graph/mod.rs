@@ -0,0 +1,385 @@
+pub mod passes;
+
+use std::collections::{BTreeSet, HashMap};
+use anyhow::{bail, Result};
+
+use crate::state::State;
+use crate::texture::Texture;
+
+// The frame's final colour target (swapchain image or offscreen texture). Always
+// available, handed to `RenderGraph::execute()' every frame.
+pub const SURFACE: &str = "surface";
+
+// How big a transient texture is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureSize {
+    // Follows the surface, scaled (e.g. 0.5 for a half resolution bloom target).
+    Surface { scale: f32 },
+    Fixed { width: u32, height: u32 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextureDesc {
+    pub size: TextureSize,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TextureDesc {
+    // A surface sized attachment that later passes can sample.
+    pub fn attachment(format: wgpu::TextureFormat) -> Self {
+        Self {
+            size: TextureSize::Surface { scale: 1.0 },
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferDesc {
+    pub size: wgpu::BufferAddress,
+    pub usage: wgpu::BufferUsages,
+}
+
+enum Resource {
+    Texture(TextureDesc),
+    Buffer(BufferDesc),
+    // Owned outside the graph (e.g. the shadow map), only used for ordering.
+    External,
+}
+
+// A node of the graph. Passes name the resources they touch, the graph runs writers
+// before readers (and writers of the same resource in the order they were added).
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    fn reads(&self) -> Vec<&str> {
+        vec![]
+    }
+
+    fn writes(&self) -> Vec<&str>;
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext);
+}
+
+// What a pass gets to work with while recording.
+pub struct PassContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub state: &'a State,
+    graph: &'a RenderGraph,
+    surface: &'a wgpu::TextureView,
+}
+
+impl PassContext<'_> {
+    // Any declared texture, or the frame's surface.
+    pub fn view(&self, name: &str) -> Option<&wgpu::TextureView> {
+        if name == SURFACE {
+            return Some(self.surface);
+        }
+
+        self.graph.textures.get(name).map(|texture| &texture.view)
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&Texture> {
+        self.graph.textures.get(name)
+    }
+
+    pub fn buffer(&self, name: &str) -> Option<&wgpu::Buffer> {
+        self.graph.buffers.get(name)
+    }
+
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.graph.surface_format
+    }
+}
+
+// Owns the frame's passes and the transient textures and buffers between them. Transient
+// textures are reallocated whenever the surface is resized.
+pub struct RenderGraph {
+    resources: HashMap<String, Resource>,
+    passes: Vec<Box<dyn Pass>>,
+    // Indices into `passes', in execution order.
+    order: Vec<usize>,
+    textures: HashMap<String, Texture>,
+    buffers: HashMap<String, wgpu::Buffer>,
+    surface_size: (u32, u32),
+    surface_format: wgpu::TextureFormat,
+}
+
+impl RenderGraph {
+    pub fn new(width: u32, height: u32, surface_format: wgpu::TextureFormat) -> Self {
+        Self {
+            resources: HashMap::new(),
+            passes: vec![],
+            order: vec![],
+            textures: HashMap::new(),
+            buffers: HashMap::new(),
+            surface_size: (width, height),
+            surface_format,
+        }
+    }
+
+    // Declares (or redeclares) a transient texture and allocates it.
+    pub fn add_texture(&mut self, device: &wgpu::Device, name: &str, desc: TextureDesc) {
+        self.textures.insert(name.to_owned(), self.create_texture(device, name, &desc));
+        self.resources.insert(name.to_owned(), Resource::Texture(desc));
+    }
+
+    pub fn add_buffer(&mut self, device: &wgpu::Device, name: &str, desc: BufferDesc) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(name),
+            size: desc.size,
+            usage: desc.usage,
+            mapped_at_creation: false,
+        });
+
+        self.buffers.insert(name.to_owned(), buffer);
+        self.resources.insert(name.to_owned(), Resource::Buffer(desc));
+    }
+
+    pub fn add_external(&mut self, name: &str) {
+        self.resources.insert(name.to_owned(), Resource::External);
+    }
+
+    pub fn texture_desc(&self, name: &str) -> Option<&TextureDesc> {
+        match self.resources.get(name) {
+            Some(Resource::Texture(desc)) => Some(desc),
+            _ => None,
+        }
+    }
+
+    pub fn buffer_desc(&self, name: &str) -> Option<&BufferDesc> {
+        match self.resources.get(name) {
+            Some(Resource::Buffer(desc)) => Some(desc),
+            _ => None,
+        }
+    }
+
+    // Every resource the pass names has to be declared first. Fails (leaving the graph
+    // as it was) if the pass would create a cycle.
+    pub fn add_pass<P: Pass + 'static>(&mut self, pass: P) -> Result<()> {
+        for name in pass.reads().into_iter().chain(pass.writes()) {
+            if name != SURFACE && !self.resources.contains_key(name) {
+                bail!("Pass `{}' uses undeclared resource `{}'!", pass.name(), name);
+            }
+        }
+
+        self.passes.push(Box::new(pass));
+
+        match self.compile() {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            },
+            Err(error) => {
+                self.passes.pop();
+                Err(error)
+            },
+        }
+    }
+
+    pub fn remove_pass(&mut self, name: &str) -> bool {
+        let len = self.passes.len();
+        self.passes.retain(|pass| pass.name() != name);
+
+        // Removing passes can't introduce cycles.
+        self.order = self.compile().unwrap_or_default();
+
+        self.passes.len() != len
+    }
+
+    pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+        self.order.iter().map(move |index| self.passes[*index].name())
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.surface_size = (width, height);
+
+        let resized = self.resources.iter()
+            .filter_map(|(name, resource)| match resource {
+                Resource::Texture(desc) if matches!(desc.size, TextureSize::Surface { .. }) => {
+                    Some((name.clone(), self.create_texture(device, name, desc)))
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        self.textures.extend(resized);
+    }
+
+    pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, state: &State, surface: &wgpu::TextureView) {
+        let context = PassContext {
+            device: &state.device,
+            queue: &state.queue,
+            state,
+            graph: self,
+            surface,
+        };
+
+        for index in self.order.iter() {
+            self.passes[*index].execute(encoder, &context);
+        }
+    }
+
+    // Kahn's algorithm over writer -> reader and writer -> later writer edges, picking the
+    // earliest added pass whenever there's a choice.
+    fn compile(&self) -> Result<Vec<usize>> {
+        let count = self.passes.len();
+        let mut edges = vec![vec![]; count];
+        let mut incoming = vec![0; count];
+        let mut writers: HashMap<&str, Vec<usize>> = HashMap::new();
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for name in pass.writes() {
+                writers.entry(name).or_default().push(index);
+            }
+        }
+
+        let mut add_edge = |from: usize, to: usize, edges: &mut Vec<Vec<usize>>| {
+            if from != to && !edges[from].contains(&to) {
+                edges[from].push(to);
+                incoming[to] += 1;
+            }
+        };
+
+        for pass_writers in writers.values() {
+            for pair in pass_writers.windows(2) {
+                add_edge(pair[0], pair[1], &mut edges);
+            }
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            for name in pass.reads() {
+                for writer in writers.get(name).into_iter().flatten() {
+                    add_edge(*writer, index, &mut edges);
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(count);
+        let mut ready = (0..count).filter(|index| incoming[*index] == 0).collect::<BTreeSet<_>>();
+
+        while let Some(index) = ready.pop_first() {
+            order.push(index);
+
+            for next in edges[index].iter() {
+                incoming[*next] -= 1;
+                if incoming[*next] == 0 {
+                    ready.insert(*next);
+                }
+            }
+        }
+
+        if order.len() != count {
+            bail!("Render graph contains a cycle!");
+        }
+
+        Ok(order)
+    }
+
+    fn create_texture(&self, device: &wgpu::Device, name: &str, desc: &TextureDesc) -> Texture {
+        let (width, height) = match desc.size {
+            TextureSize::Surface { scale } => (
+                ((self.surface_size.0 as f32 * scale) as u32).max(1),
+                ((self.surface_size.1 as f32 * scale) as u32).max(1),
+            ),
+            TextureSize::Fixed { width, height } => (width.max(1), height.max(1)),
+        };
+
+        Texture::create_render_target(device, width, height, desc.format, desc.usage, desc.sample_count, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only declares what it touches, enough for ordering.
+    struct TestPass {
+        name: &'static str,
+        reads: Vec<&'static str>,
+        writes: Vec<&'static str>,
+    }
+
+    impl Pass for TestPass {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn reads(&self) -> Vec<&str> {
+            self.reads.clone()
+        }
+
+        fn writes(&self) -> Vec<&str> {
+            self.writes.clone()
+        }
+
+        fn execute(&self, _encoder: &mut wgpu::CommandEncoder, _context: &PassContext) {}
+    }
+
+    fn pass(name: &'static str, reads: &[&'static str], writes: &[&'static str]) -> TestPass {
+        TestPass { name, reads: reads.to_vec(), writes: writes.to_vec() }
+    }
+
+    fn graph(resources: &[&str]) -> RenderGraph {
+        let mut graph = RenderGraph::new(1, 1, Texture::HDR_FORMAT);
+        for name in resources {
+            graph.add_external(name);
+        }
+
+        graph
+    }
+
+    #[test]
+    fn writers_run_before_readers() {
+        let mut graph = graph(&["shadow", "hdr"]);
+        graph.add_pass(pass("tonemap", &["hdr"], &[SURFACE])).unwrap();
+        graph.add_pass(pass("scene", &["shadow"], &["hdr"])).unwrap();
+        graph.add_pass(pass("shadow", &[], &["shadow"])).unwrap();
+
+        assert_eq!(graph.pass_names().collect::<Vec<_>>(), ["shadow", "scene", "tonemap"]);
+    }
+
+    #[test]
+    fn writers_of_one_resource_keep_their_order() {
+        let mut graph = graph(&["hdr"]);
+        graph.add_pass(pass("scene", &[], &["hdr"])).unwrap();
+        graph.add_pass(pass("skybox", &[], &["hdr"])).unwrap();
+        graph.add_pass(pass("transparent", &[], &["hdr"])).unwrap();
+
+        assert_eq!(graph.pass_names().collect::<Vec<_>>(), ["scene", "skybox", "transparent"]);
+    }
+
+    #[test]
+    fn cycles_are_rejected_and_leave_the_graph_alone() {
+        let mut graph = graph(&["a", "b"]);
+        graph.add_pass(pass("first", &["b"], &["a"])).unwrap();
+
+        assert!(graph.add_pass(pass("second", &["a"], &["b"])).is_err());
+        assert_eq!(graph.pass_names().collect::<Vec<_>>(), ["first"]);
+    }
+
+    #[test]
+    fn undeclared_resources_are_rejected() {
+        let mut graph = graph(&[]);
+
+        assert!(graph.add_pass(pass("scene", &["missing"], &[SURFACE])).is_err());
+        assert_eq!(graph.pass_names().count(), 0);
+    }
+
+    #[test]
+    fn removing_a_pass_keeps_the_rest_ordered() {
+        let mut graph = graph(&["hdr"]);
+        graph.add_pass(pass("tonemap", &["hdr"], &[SURFACE])).unwrap();
+        graph.add_pass(pass("scene", &[], &["hdr"])).unwrap();
+        graph.add_pass(pass("overlay", &[], &[SURFACE])).unwrap();
+
+        assert!(graph.remove_pass("scene"));
+        assert!(!graph.remove_pass("scene"));
+        assert_eq!(graph.pass_names().collect::<Vec<_>>(), ["tonemap", "overlay"]);
+    }
+}
@@ -0,0 +1,502 @@
+use std::ops::Range;
+use std::rc::Rc;
+use cgmath::prelude::*;
+
+use crate::render_graph::{Pass, PassContext, SURFACE};
+use crate::post_process::{POST_PING, POST_PONG, BLOOM_A, BLOOM_B};
+use crate::culling::scene_meshes;
+use crate::indirect::{DrawIndexedIndirectArgs, DrawMode, IndirectBatch};
+use crate::vertex::VertexLayout;
+use crate::ecs::component::mesh::MeshComponent;
+use crate::ecs::component::instance::{InstanceComponent, InstanceRaw};
+use crate::ecs::scene::Scene;
+use crate::material::{AlphaMode, Material};
+use crate::state::State;
+
+pub const SHADOW_MAP: &str = "shadow_map";
+pub const DEPTH: &str = "depth";
+pub const HDR: &str = "hdr";
+// The scene pass renders here when MSAA is on, resolving into `HDR'.
+pub const HDR_MSAA: &str = "hdr_msaa";
+// `State::indirect_draws'' arguments buffer.
+pub const INDIRECT_ARGS: &str = "indirect_args";
+
+enum DrawCall<'a> {
+    // Runs of one mesh's visible instances.
+    Direct(&'a MeshComponent, &'a InstanceComponent, Vec<Range<u32>>),
+    // A whole batch, through `IndirectDraws::args_buffer()'.
+    Indirect(IndirectBatch<'a>),
+}
+
+// Renders the active scene into the shadow map's cascades.
+pub struct ShadowPass;
+
+impl Pass for ShadowPass {
+    fn name(&self) -> &str {
+        "shadow"
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![SHADOW_MAP]
+    }
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext) {
+        if let Some(scene) = context.state.get_active_scene() {
+            context.state.shadow_map.render(encoder, context.device, &context.state.pipeline_cache, scene);
+        }
+    }
+}
+
+// Gathers the active scene's instances for the indirect draws, culling them on the GPU
+// with `DrawMode::GpuCulled' (does nothing with `DrawMode::Direct').
+pub struct CullPass;
+
+impl Pass for CullPass {
+    fn name(&self) -> &str {
+        "cull"
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![INDIRECT_ARGS]
+    }
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext) {
+        if let Some(scene) = context.state.get_active_scene() {
+            context.state.indirect_draws.cull(encoder, scene);
+        }
+    }
+}
+
+// Draws the active scene's opaque meshes into `target', clearing it first. Instances outside
+// the view (see `State::visibility') are skipped, draws are indirect (a batch of meshes at a
+// time, see `IndirectDraws') unless `RenderSettings::draw_mode' is `DrawMode::Direct'. With
+// MSAA on (`RenderSettings::sample_count') it draws into `multisampled_target' and resolves
+// into `target'.
+pub struct ScenePass {
+    pub target: String,
+    pub multisampled_target: Option<String>,
+}
+
+impl ScenePass {
+    pub fn new(target: &str) -> Self {
+        Self { target: target.to_owned(), multisampled_target: None }
+    }
+
+    pub fn multisampled(target: &str, multisampled_target: &str) -> Self {
+        Self { target: target.to_owned(), multisampled_target: Some(multisampled_target.to_owned()) }
+    }
+}
+
+impl Pass for ScenePass {
+    fn name(&self) -> &str {
+        "scene"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![SHADOW_MAP, INDIRECT_ARGS]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        let mut writes = vec![self.target.as_str(), DEPTH];
+        writes.extend(self.multisampled_target.as_deref());
+
+        writes
+    }
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext) {
+        let state = context.state;
+        let (view, resolve_target, depth_view) = match scene_targets(context, &self.target, self.multisampled_target.as_deref()) {
+            Some(targets) => targets,
+            None => return,
+        };
+
+        // Pipelines are looked up before the pass starts, it borrows them until it ends.
+        let mut draws = vec![];
+        if let Some(scene) = state.get_active_scene() {
+            if state.indirect_draws.mode() == DrawMode::Direct {
+                for (key, mesh_component, instance_component) in scene_meshes(scene) {
+                    let material = mesh_material(state, scene, mesh_component);
+                    if material.alpha_mode != AlphaMode::Opaque {
+                        continue;
+                    }
+
+                    let instance_ranges = state.visibility.instance_ranges(key, instance_component.len() as u32);
+                    if instance_ranges.is_empty() {
+                        continue;
+                    }
+
+                    if let Some(render_pipeline) = mesh_pipeline(context, mesh_component.layout(), material) {
+                        draws.push((material, render_pipeline, DrawCall::Direct(mesh_component, instance_component, instance_ranges)));
+                    }
+                }
+            } else {
+                for batch in state.indirect_draws.batches() {
+                    let material = batch.material
+                        .and_then(|handle| scene.material(handle))
+                        .unwrap_or(&state.default_material);
+
+                    if let Some(render_pipeline) = mesh_pipeline(context, batch.layout, material) {
+                        draws.push((material, render_pipeline, DrawCall::Indirect(batch)));
+                    }
+                }
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[
+                // This is what @location(0) in the fragment shader targets
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(
+                            wgpu::Color {
+                                r: 0.0,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 1.0,
+                            }
+                        ),
+                        store: true,
+                    }
+                })
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_bind_group(0, &state.camera_bind_group, &[]);
+
+        let args_buffer = state.indirect_draws.args_buffer();
+        for (material, render_pipeline, call) in draws.iter() {
+            match call {
+                DrawCall::Direct(mesh_component, instance_component, instance_ranges) => {
+                    bind_mesh(&mut render_pass, state, mesh_component, material, render_pipeline, &instance_component.instance_buffer);
+                    for instances in instance_ranges.iter() {
+                        render_pass.draw_indexed(0..mesh_component.num_indices, 0, instances.clone());
+                    }
+                },
+                DrawCall::Indirect(batch) => {
+                    bind_material(&mut render_pass, state, batch.layout, material, render_pipeline);
+                    render_pass.set_vertex_buffer(0, batch.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(batch.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+                    if state.indirect_draws.multi_draw() {
+                        render_pass.set_vertex_buffer(1, batch.instance_buffer.slice(..));
+                        render_pass.multi_draw_indexed_indirect(args_buffer, batch.offset, batch.draw_count);
+                        continue;
+                    }
+
+                    // Every draw's instances start at 0, wherever its instance buffer is bound.
+                    for (draw, instances) in batch.instances.iter().enumerate() {
+                        if instances.is_empty() {
+                            continue;
+                        }
+
+                        let args_offset = batch.offset + (draw * std::mem::size_of::<DrawIndexedIndirectArgs>()) as wgpu::BufferAddress;
+                        let instance_offset = instances.start as wgpu::BufferAddress * std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+                        render_pass.set_vertex_buffer(1, batch.instance_buffer.slice(instance_offset..));
+                        render_pass.draw_indexed_indirect(args_buffer, args_offset);
+                    }
+                },
+            }
+        }
+    }
+}
+
+// Fills whatever `ScenePass' left empty (where the depth buffer is still clear) with
+// `State::skybox'. Does nothing without a skybox.
+pub struct SkyboxPass {
+    pub target: String,
+    pub multisampled_target: Option<String>,
+}
+
+impl SkyboxPass {
+    pub fn new(target: &str) -> Self {
+        Self { target: target.to_owned(), multisampled_target: None }
+    }
+
+    pub fn multisampled(target: &str, multisampled_target: &str) -> Self {
+        Self { target: target.to_owned(), multisampled_target: Some(multisampled_target.to_owned()) }
+    }
+}
+
+impl Pass for SkyboxPass {
+    fn name(&self) -> &str {
+        "skybox"
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        let mut writes = vec![self.target.as_str(), DEPTH];
+        writes.extend(self.multisampled_target.as_deref());
+
+        writes
+    }
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext) {
+        let state = context.state;
+        let skybox = match &state.skybox {
+            Some(skybox) => skybox,
+            None => return,
+        };
+
+        let render_pipeline = match state.pipeline_cache.get(context.device, &state.skybox_pipeline_key()) {
+            Ok(render_pipeline) => render_pipeline,
+            Err(error) => {
+                log::warn!("Skipping skybox: {}", error);
+                return;
+            },
+        };
+
+        let (view, resolve_target, depth_view) = match scene_targets(context, &self.target, self.multisampled_target.as_deref()) {
+            Some(targets) => targets,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Skybox Render Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }
+                })
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(&render_pipeline);
+        render_pass.set_bind_group(0, &state.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &skybox.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// Draws the active scene's alpha blended meshes on top of what `ScenePass' left in `target'
+// and the depth buffer. Every instance is drawn on its own, sorted back to front by its
+// distance from the camera, and depth is tested but not written.
+pub struct TransparentPass {
+    pub target: String,
+    pub multisampled_target: Option<String>,
+}
+
+impl TransparentPass {
+    pub fn new(target: &str) -> Self {
+        Self { target: target.to_owned(), multisampled_target: None }
+    }
+
+    pub fn multisampled(target: &str, multisampled_target: &str) -> Self {
+        Self { target: target.to_owned(), multisampled_target: Some(multisampled_target.to_owned()) }
+    }
+}
+
+impl Pass for TransparentPass {
+    fn name(&self) -> &str {
+        "transparent"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![SHADOW_MAP]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        let mut writes = vec![self.target.as_str(), DEPTH];
+        writes.extend(self.multisampled_target.as_deref());
+
+        writes
+    }
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext) {
+        let state = context.state;
+        let scene = match state.get_active_scene() {
+            Some(scene) => scene,
+            None => return,
+        };
+
+        let camera_position = state.camera.position;
+        let mut draws = vec![];
+        for (key, mesh_component, instance_component) in scene_meshes(scene) {
+            let material = mesh_material(state, scene, mesh_component);
+            if material.alpha_mode != AlphaMode::Blend {
+                continue;
+            }
+
+            let instance_ranges = state.visibility.instance_ranges(key, instance_component.len() as u32);
+            if instance_ranges.is_empty() {
+                continue;
+            }
+
+            let render_pipeline = match mesh_pipeline(context, mesh_component.layout(), material) {
+                Some(render_pipeline) => render_pipeline,
+                None => continue,
+            };
+
+            for index in instance_ranges.into_iter().flatten() {
+                let instance = &instance_component.instances()[index as usize];
+                let position = instance_component.world.transform_point(cgmath::Point3::from_vec(instance.position));
+                let distance = position.distance2(camera_position);
+                draws.push((distance, index, mesh_component, instance_component, material, render_pipeline.clone()));
+            }
+        }
+
+        if draws.is_empty() {
+            return;
+        }
+
+        draws.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let (view, resolve_target, depth_view) = match scene_targets(context, &self.target, self.multisampled_target.as_deref()) {
+            Some(targets) => targets,
+            None => return,
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent Render Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }
+                })
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_bind_group(0, &state.camera_bind_group, &[]);
+
+        for (_, index, mesh_component, instance_component, material, render_pipeline) in draws.iter() {
+            bind_mesh(&mut render_pass, state, mesh_component, material, render_pipeline, &instance_component.instance_buffer);
+            render_pass.draw_indexed(0..mesh_component.num_indices, 0, *index..*index + 1);
+        }
+    }
+}
+
+// Takes `input' through `State::post_process' onto the surface.
+pub struct PostProcessPass {
+    pub input: String,
+}
+
+impl PostProcessPass {
+    pub fn new(input: &str) -> Self {
+        Self { input: input.to_owned() }
+    }
+}
+
+impl Pass for PostProcessPass {
+    fn name(&self) -> &str {
+        "post_process"
+    }
+
+    fn reads(&self) -> Vec<&str> {
+        vec![&self.input]
+    }
+
+    fn writes(&self) -> Vec<&str> {
+        vec![SURFACE, POST_PING, POST_PONG, BLOOM_A, BLOOM_B]
+    }
+
+    fn execute(&self, encoder: &mut wgpu::CommandEncoder, context: &PassContext) {
+        context.state.post_process.render(encoder, context, &self.input);
+    }
+}
+
+// The scene passes' colour attachment, its resolve target and the depth buffer.
+fn scene_targets<'a>(
+    context: &'a PassContext,
+    target: &str,
+    multisampled_target: Option<&str>,
+) -> Option<(&'a wgpu::TextureView, Option<&'a wgpu::TextureView>, &'a wgpu::TextureView)> {
+    let (target_view, depth_view) = match (context.view(target), context.view(DEPTH)) {
+        (Some(view), Some(depth_view)) => (view, depth_view),
+        _ => return None,
+    };
+
+    let multisampled_view = match multisampled_target {
+        Some(name) if context.state.render_settings.sample_count > 1 => context.view(name),
+        _ => None,
+    };
+
+    match multisampled_view {
+        Some(multisampled_view) => Some((multisampled_view, Some(target_view), depth_view)),
+        None => Some((target_view, None, depth_view)),
+    }
+}
+
+fn mesh_material<'a>(state: &'a State, scene: &'a Scene, mesh_component: &MeshComponent) -> &'a Material {
+    mesh_component.material
+        .and_then(|handle| scene.material(handle))
+        .unwrap_or(&state.default_material)
+}
+
+fn mesh_pipeline(context: &PassContext, layout: VertexLayout, material: &Material) -> Option<Rc<wgpu::RenderPipeline>> {
+    let key = context.state.scene_pipeline_key(layout, material.shading, material.alpha_mode);
+
+    match context.state.pipeline_cache.get(context.device, &key) {
+        Ok(render_pipeline) => Some(render_pipeline),
+        Err(error) => {
+            log::warn!("Skipping mesh: {}", error);
+            None
+        },
+    }
+}
+
+// Binds everything but the camera for drawing `mesh_component', the draw call itself is up
+// to the caller.
+fn bind_mesh<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    state: &'a State,
+    mesh_component: &'a MeshComponent,
+    material: &'a Material,
+    render_pipeline: &'a wgpu::RenderPipeline,
+    instance_buffer: &'a wgpu::Buffer,
+) {
+    bind_material(render_pass, state, mesh_component.layout(), material, render_pipeline);
+
+    render_pass.set_vertex_buffer(0, mesh_component.vertex_buffer.slice(..));
+    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+    render_pass.set_index_buffer(mesh_component.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+}
+
+// The pipeline and everything but the camera for drawing meshes of `layout' with `material'.
+fn bind_material<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    state: &'a State,
+    layout: VertexLayout,
+    material: &'a Material,
+    render_pipeline: &'a wgpu::RenderPipeline,
+) {
+    render_pass.set_pipeline(render_pipeline);
+
+    if layout.has_normals() {
+        render_pass.set_bind_group(1, &material.bind_group, &[]);
+        render_pass.set_bind_group(2, &state.light_bind_group, &[]);
+        render_pass.set_bind_group(3, &state.shadow_map.bind_group, &[]);
+    }
+}
@@ -0,0 +1,410 @@
+use std::num::NonZeroU32;
+use cgmath::prelude::*;
+use cgmath::{ortho, Matrix4, Point3, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::camera::{Camera, Projection, OPENGL_TO_WGPU_MATRIX};
+use crate::texture::Texture;
+use crate::pipeline_cache::{DepthKey, PipelineCache, PipelineKey};
+use crate::vertex::VertexLayout;
+use crate::ecs::scene::Scene;
+use crate::ecs::component::mesh::MeshComponent;
+use crate::ecs::component::instance::{InstanceComponent, InstanceRaw};
+
+// Has to match `MAX_CASCADES' in res/shaders/h_shadow.frag.
+pub const MAX_CASCADES: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    // Width and height of each cascade's depth map.
+    pub resolution: u32,
+    // 1 renders a single shadow map covering `max_distance', more split the view
+    // frustum into cascades that get coarser with distance. At most `MAX_CASCADES'.
+    pub cascade_count: u32,
+    // Nothing further from the camera than this receives shadows.
+    pub max_distance: f32,
+    // Blends the cascade splits between uniform (0.0) and logarithmic (1.0).
+    pub split_lambda: f32,
+    pub depth_bias: f32,
+    // Samples (2 * radius + 1)^2 texels around each lookup, 0 only uses the sampler's
+    // own 2x2 filtering.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 2048,
+            cascade_count: 1,
+            max_distance: 100.0,
+            split_lambda: 0.75,
+            depth_bias: 0.002,
+            pcf_radius: 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowUniform {
+    cascade_view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    cascade_splits: [f32; 4],
+    // Cascade count, depth bias, PCF radius and texel size.
+    params: [f32; 4],
+}
+
+// Depth maps rendered from the scene's shadow casting (directional) light, sampled by
+// the lit shaders at bind group 3.
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    pub texture: Texture,
+    cascade_views: Vec<wgpu::TextureView>,
+    uniform: ShadowUniform,
+    uniform_buffer: wgpu::Buffer,
+    cascade_buffers: Vec<wgpu::Buffer>,
+    cascade_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl ShadowMap {
+    // Registers the shadow pass's shader and layout with `pipeline_cache'.
+    pub fn new(device: &wgpu::Device, pipeline_cache: &mut PipelineCache, settings: ShadowSettings) -> Self {
+        let settings = Self::clamp_settings(settings);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("shadow_bind_group_layout"),
+        });
+
+        let cascade_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("shadow_cascade_bind_group_layout"),
+        });
+
+        let uniform = ShadowUniform {
+            cascade_view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+            cascade_splits: [0.0; 4],
+            params: [0.0; 4],
+        };
+
+        let uniform_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Shadow Buffer"),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let (cascade_buffers, cascade_bind_groups) = (0..MAX_CASCADES).map(|cascade| {
+            let matrix: [[f32; 4]; 4] = Matrix4::identity().into();
+            let buffer = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some(format!("Shadow Cascade Buffer ({})", cascade).as_str()),
+                    contents: bytemuck::cast_slice(&[matrix]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                }
+            );
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &cascade_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }
+                ],
+                label: Some(format!("shadow_cascade_bind_group ({})", cascade).as_str()),
+            });
+
+            (buffer, bind_group)
+        }).unzip();
+
+        let shadow_vertex_shader = create_spv_shader!(device, "../target/shadow_vertex.spv", "shadow_vertex");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[
+                &cascade_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        pipeline_cache.add_shader("shadow_vertex", shadow_vertex_shader);
+        pipeline_cache.add_layout("shadow", pipeline_layout);
+
+        let (texture, cascade_views, bind_group) =
+            Self::create_targets(device, &bind_group_layout, &uniform_buffer, settings.resolution);
+
+        Self {
+            settings,
+            bind_group_layout,
+            bind_group,
+            texture,
+            cascade_views,
+            uniform,
+            uniform_buffer,
+            cascade_buffers,
+            cascade_bind_groups,
+        }
+    }
+
+    // Only recreates the depth maps when the resolution changes.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = Self::clamp_settings(settings);
+
+        if settings.resolution != self.settings.resolution {
+            let (texture, cascade_views, bind_group) =
+                Self::create_targets(device, &self.bind_group_layout, &self.uniform_buffer, settings.resolution);
+
+            self.texture = texture;
+            self.cascade_views = cascade_views;
+            self.bind_group = bind_group;
+        }
+
+        self.settings = settings;
+    }
+
+    // Fits a cascade around each slice of the camera's frustum, looking down `direction'.
+    // Without a direction (no shadow caster) or with shadows disabled every fragment is
+    // treated as lit.
+    pub fn update(&mut self, queue: &wgpu::Queue, direction: Option<Vector3<f32>>, camera: &Camera, projection: &Projection) {
+        let settings = self.settings;
+        let direction = match direction {
+            Some(direction) if settings.enabled && direction.magnitude2() > 0.0 => direction.normalize(),
+            _ => {
+                self.uniform.params[0] = 0.0;
+                queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+                return;
+            },
+        };
+
+        let znear = projection.znear();
+        let zfar = projection.zfar().min(settings.max_distance).max(znear + 0.001);
+        let view = camera.calc_matrix();
+        let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+
+        let mut slice_near = znear;
+        for cascade in 0..settings.cascade_count as usize {
+            let p = (cascade + 1) as f32 / settings.cascade_count as f32;
+            let logarithmic = znear * (zfar / znear).powf(p);
+            let uniform = znear + (zfar - znear) * p;
+            let slice_far = settings.split_lambda * logarithmic + (1.0 - settings.split_lambda) * uniform;
+
+            // Bounding sphere of the slice, so the cascade doesn't change size as the
+            // camera turns.
+            let corners = frustum_corners(projection.calc_matrix_with_range(slice_near, slice_far) * view);
+            let center = corners.iter().fold(Vector3::zero(), |sum, corner| sum + corner) / corners.len() as f32;
+            let radius = corners.iter().map(|corner| (corner - center).magnitude()).fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // Back off far enough to catch casters between the light and the slice.
+            let eye = Point3::from_vec(center - direction * (radius + settings.max_distance));
+            let light_view = Matrix4::look_to_rh(eye, direction, up);
+            let light_projection = ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + settings.max_distance);
+            let mut view_proj = OPENGL_TO_WGPU_MATRIX * light_projection * light_view;
+
+            // Snap to whole texels, otherwise shadow edges shimmer as the camera moves.
+            let half_resolution = settings.resolution as f32 / 2.0;
+            let origin = view_proj * Vector4::unit_w() * half_resolution;
+            view_proj.w.x += (origin.x.round() - origin.x) / half_resolution;
+            view_proj.w.y += (origin.y.round() - origin.y) / half_resolution;
+
+            let matrix: [[f32; 4]; 4] = view_proj.into();
+            self.uniform.cascade_view_proj[cascade] = matrix;
+            self.uniform.cascade_splits[cascade] = slice_far;
+            queue.write_buffer(&self.cascade_buffers[cascade], 0, bytemuck::cast_slice(&[matrix]));
+
+            slice_near = slice_far;
+        }
+
+        self.uniform.params = [
+            settings.cascade_count as f32,
+            settings.depth_bias,
+            settings.pcf_radius as f32,
+            1.0 / settings.resolution as f32,
+        ];
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.uniform.params[0] > 0.0
+    }
+
+    // Depth only. Both faces are drawn so open meshes (planes, quads) still cast shadows,
+    // the slope scaled bias keeps them from shadowing themselves. Every vertex layout
+    // starts with the position, which is all the shadow pass reads.
+    pub fn pipeline_key(layout: VertexLayout) -> PipelineKey {
+        PipelineKey {
+            vertex_shader: "shadow_vertex",
+            fragment_shader: None,
+            layout: "shadow",
+            vertex_buffers: vec![(&layout.desc()).into(), (&InstanceRaw::desc()).into()],
+            color_targets: vec![],
+            depth: Some(DepthKey {
+                format: Texture::DEPTH_FORMAT,
+                write_enabled: true,
+                compare: wgpu::CompareFunction::LessEqual,
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                }.into(),
+            }),
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            sample_count: 1,
+        }
+    }
+
+    // Records one depth pass per cascade.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, device: &wgpu::Device, pipeline_cache: &PipelineCache, scene: &Scene) {
+        if !self.is_active() {
+            return;
+        }
+
+        // Pipelines are looked up before the passes start, they borrow them until they end.
+        let draws = scene.query2::<MeshComponent, InstanceComponent>()
+            .filter_map(|(_, mesh_component, instance_component)| {
+                match pipeline_cache.get(device, &Self::pipeline_key(mesh_component.layout())) {
+                    Ok(pipeline) => Some((mesh_component, instance_component, pipeline)),
+                    Err(error) => {
+                        log::warn!("Skipping shadow caster: {}", error);
+                        None
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for cascade in 0..self.settings.cascade_count as usize {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.cascade_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_bind_group(0, &self.cascade_bind_groups[cascade], &[]);
+
+            for (mesh_component, instance_component, pipeline) in draws.iter() {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_vertex_buffer(0, mesh_component.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, instance_component.instance_buffer.slice(..));
+                render_pass.set_index_buffer(mesh_component.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh_component.num_indices, 0, 0..instance_component.len() as _);
+            }
+        }
+    }
+
+    fn clamp_settings(settings: ShadowSettings) -> ShadowSettings {
+        ShadowSettings {
+            resolution: settings.resolution.max(1),
+            cascade_count: settings.cascade_count.clamp(1, MAX_CASCADES as u32),
+            ..settings
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        resolution: u32,
+    ) -> (Texture, Vec<wgpu::TextureView>, wgpu::BindGroup) {
+        let texture = Texture::create_shadow_map_texture(device, resolution, MAX_CASCADES as u32, "shadow_map");
+
+        let cascade_views = (0..MAX_CASCADES as u32).map(|layer| {
+            texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("shadow_cascade_view"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: NonZeroU32::new(1),
+                ..Default::default()
+            })
+        }).collect();
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("shadow_bind_group"),
+        });
+
+        (texture, cascade_views, bind_group)
+    }
+}
+
+// World space corners of the frustum described by `view_proj'.
+fn frustum_corners(view_proj: Matrix4<f32>) -> Vec<Vector3<f32>> {
+    let inverse = match view_proj.invert() {
+        Some(inverse) => inverse,
+        None => return vec![Vector3::zero()],
+    };
+
+    let mut corners = Vec::with_capacity(8);
+    for x in [-1.0, 1.0] {
+        for y in [-1.0, 1.0] {
+            for z in [0.0, 1.0] {
+                let corner = inverse * Vector4::new(x, y, z, 1.0);
+                corners.push(corner.truncate() / corner.w);
+            }
+        }
+    }
+
+    corners
+}
@@ -0,0 +1,56 @@
+use std::rc::Rc;
+
+use crate::texture::Texture;
+
+// An environment cube map drawn behind the scene by `SkyboxPass', bound at group 1 of the
+// skybox pipeline.
+pub struct Skybox {
+    pub cubemap: Rc<Texture>,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("skybox_bind_group_layout"),
+        })
+    }
+
+    // `cubemap' has to be a cube map, see `Texture::create_cubemap()'.
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, cubemap: Rc<Texture>) -> Self {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cubemap.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        });
+
+        Self { cubemap, bind_group }
+    }
+}
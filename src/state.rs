@@ -1,18 +1,30 @@
-use log::warn;
+use std::rc::Rc;
+use std::sync::mpsc;
+use anyhow::{anyhow, bail, Result};
 use wgpu::util::DeviceExt;
 use winit::{
     window::Window,
-    event::{KeyboardInput, WindowEvent, MouseButton},
+    event::{ElementState, KeyboardInput, WindowEvent, MouseButton, VirtualKeyCode},
 };
 
-use crate::vertex::{PureVertex, Vertex};
+use crate::vertex::VertexLayout;
 use crate::shader::create_spv_shader;
 use crate::camera::{Camera, CameraUniform, CameraController, Projection};
+use crate::culling::{Frustum, Visibility};
+use crate::indirect::{DrawMode, IndirectDraws, MULTI_DRAW_FEATURES};
 use crate::texture::Texture;
+use crate::material::{AlphaMode, Material, MaterialLayouts, ShadingModel};
+use crate::shadow::{ShadowMap, ShadowSettings};
+use crate::render_graph::{RenderGraph, TextureDesc, TextureSize, passes::{CullPass, PostProcessPass, ScenePass, ShadowPass, SkyboxPass, TransparentPass, DEPTH, HDR, HDR_MSAA, INDIRECT_ARGS, SHADOW_MAP}};
+use crate::post_process::PostProcessStack;
+use crate::skybox::Skybox;
+use crate::pipeline_cache::{ColorTargetKey, DepthBiasKey, DepthKey, PipelineCache, PipelineKey};
 use crate::ecs::{
     scene::Scene,
-    component::mesh::MeshComponent,
-    component::instance::{InstanceRaw, InstanceComponent},
+    system::{Scheduler, Stage, SystemContext},
+    component::instance::{InstanceRaw, InstanceUpload},
+    component::transform::TransformPropagation,
+    component::light::LightUniform,
 };
 
 #[cfg(target_os = "macos")]
@@ -20,11 +32,61 @@ pub const GRAPHICS_BACKEND: wgpu::Backends = wgpu::Backends::METAL;
 #[cfg(not(target_os = "macos"))]
 pub const GRAPHICS_BACKEND: wgpu::Backends = wgpu::Backends::VULKAN;
 pub const DEVICE_FEATURES: wgpu::Features = wgpu::Features::POLYGON_MODE_LINE;
-pub const DRAW_POLYGON_MODE: wgpu::PolygonMode = wgpu::PolygonMode::Fill;
+// Enabled when the adapter has them. Adapter specific format features make
+// `supported_sample_counts()' check the adapter instead of WebGPU's guarantees, the
+// multi-draw ones let indirect draws go out a batch at a time.
+pub const OPTIONAL_DEVICE_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+    .union(MULTI_DRAW_FEATURES);
+
+// Rendering options that can change while running, see `State::set_render_settings()'.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RenderSettings {
+    // Draws triangle edges only (`Features::POLYGON_MODE_LINE').
+    pub wireframe: bool,
+    // Off lets the surface present immediately, falling back to mailbox or FIFO.
+    pub vsync: bool,
+    pub backface_culling: bool,
+    // MSAA samples per pixel of the scene pass, one of `State::supported_sample_counts'.
+    pub sample_count: u32,
+    pub draw_mode: DrawMode,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            wireframe: false,
+            vsync: true,
+            backface_culling: true,
+            sample_count: 1,
+            draw_mode: DrawMode::Direct,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn polygon_mode(&self) -> wgpu::PolygonMode {
+        if self.wireframe { wgpu::PolygonMode::Line } else { wgpu::PolygonMode::Fill }
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        if self.vsync { wgpu::PresentMode::Fifo } else { wgpu::PresentMode::AutoNoVsync }
+    }
+
+    pub fn cull_mode(&self) -> Option<wgpu::Face> {
+        if self.backface_culling { Some(wgpu::Face::Back) } else { None }
+    }
+}
+
+// Where frames end up: a window's swapchain, or an offscreen texture that can be
+// read back to the CPU (see `State::new_headless()').
+pub enum RenderTarget {
+    Surface(wgpu::Surface),
+    Offscreen(Texture),
+}
 
 pub struct State {
     // Rendering
-    pub surface: wgpu::Surface,
+    pub target: RenderTarget,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
@@ -36,12 +98,29 @@ pub struct State {
     pub noise_storage_buffer: wgpu::Buffer,
     pub projection: Projection,
     pub camera_controller: CameraController,
-    pub depth_texture: Texture,
-    pub render_pipeline: wgpu::RenderPipeline,
+    pub pipeline_cache: PipelineCache,
+    pub render_settings: RenderSettings,
+    pub supported_sample_counts: Vec<u32>,
+    pub material_layouts: MaterialLayouts,
+    // Bound for textured meshes that don't reference a material of their own.
+    pub default_material: Material,
+    pub light_uniform: LightUniform,
+    pub light_buffer: wgpu::Buffer,
+    pub light_bind_group: wgpu::BindGroup,
+    pub shadow_map: ShadowMap,
+    pub skybox_bind_group_layout: wgpu::BindGroupLayout,
+    // Drawn behind the scene when set, otherwise the background stays black.
+    pub skybox: Option<Skybox>,
+    // What the scene passes draw this frame, recomputed by `update()'.
+    pub visibility: Visibility,
+    pub indirect_draws: IndirectDraws,
+    pub render_graph: RenderGraph,
+    pub post_process: PostProcessStack,
 
     // Scenes
     pub scenes: Vec<Scene>,
     pub active_scene_index: usize,
+    pub scheduler: Scheduler,
 }
 
 impl State {
@@ -67,27 +146,72 @@ impl State {
             },
         ).await.unwrap();
 
+        let format = surface.get_supported_formats(&adapter)[0];
+
+        Self::from_adapter(adapter, Some(surface), format, size).await
+    }
+
+    // Renders into an offscreen texture instead of a swapchain, for machines without a
+    // display (CI, build servers). Software adapters such as lavapipe work here too.
+    // Frames are read back with `render_to_image()'.
+    pub async fn new_headless(width: u32, height: u32) -> Self {
+        let size = winit::dpi::PhysicalSize::new(width, height);
+        let instance = wgpu::Instance::new(GRAPHICS_BACKEND);
+
+        let adapter = instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            },
+        ).await.expect("No adapter available for headless rendering!");
+
+        Self::from_adapter(adapter, None, Texture::OFFSCREEN_FORMAT, size).await
+    }
+
+    // Shared setup for windowed and headless states. Without a surface, frames go to
+    // an offscreen texture of the same size.
+    async fn from_adapter(
+        adapter: wgpu::Adapter,
+        surface: Option<wgpu::Surface>,
+        format: wgpu::TextureFormat,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> Self {
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                features: DEVICE_FEATURES,
+                features: DEVICE_FEATURES | (adapter.features() & OPTIONAL_DEVICE_FEATURES),
                 limits: wgpu::Limits::default(),
                 label: None,
             },
             None,
         ).await.unwrap();
 
+        let render_settings = RenderSettings::default();
+
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: render_settings.present_mode(),
         };
 
-        surface.configure(&device, &config);
+        let target = match surface {
+            Some(surface) => {
+                surface.configure(&device, &config);
+                RenderTarget::Surface(surface)
+            },
+            None => RenderTarget::Offscreen(Texture::create_offscreen_texture(&device, &config, "offscreen_texture")),
+        };
 
         let vertex_shader = create_spv_shader!(device, "../target/vertex.spv", "vertex");
         let fragment_shader = create_spv_shader!(device, "../target/fragment.spv", "fragment");
+        let lit_vertex_shader = create_spv_shader!(device, "../target/lit_vertex.spv", "lit_vertex");
+        let lit_fragment_shader = create_spv_shader!(device, "../target/lit_fragment.spv", "lit_fragment");
+        let tangent_vertex_shader = create_spv_shader!(device, "../target/tangent_vertex.spv", "tangent_vertex");
+        let pbr_fragment_shader = create_spv_shader!(device, "../target/pbr_fragment.spv", "pbr_fragment");
+        let skybox_vertex_shader = create_spv_shader!(device, "../target/skybox_vertex.spv", "skybox_vertex");
+        let skybox_fragment_shader = create_spv_shader!(device, "../target/skybox_fragment.spv", "skybox_fragment");
 
         let camera = Camera::new((0.0, 3.0, 6.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
         let projection = Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 5000.0);
@@ -108,7 +232,8 @@ impl State {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // The fragment stage needs the view position for specular highlights.
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -140,7 +265,8 @@ impl State {
             mapped_at_creation: false,
         });
 
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
+        let material_layouts = MaterialLayouts::new(&device);
+        let default_material = Material::from_tint(&device, &queue, &material_layouts.blinn_phong, "default", [1.0; 4]);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -151,55 +277,123 @@ impl State {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vertex_shader,
-                entry_point: "main",
-                buffers: &[
-                    PureVertex::desc(), InstanceRaw::desc(),
-                ],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fragment_shader,
-                entry_point: "main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: DRAW_POLYGON_MODE,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
+        let light_uniform = LightUniform::new();
+
+        let light_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light Buffer"),
+                contents: bytemuck::cast_slice(&[light_uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("light_bind_group_layout"),
         });
 
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("light_bind_group"),
+        });
+
+        let mut pipeline_cache = PipelineCache::new();
+        let shadow_map = ShadowMap::new(&device, &mut pipeline_cache, ShadowSettings::default());
+
+        let lit_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Lit Pipeline Layout"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &material_layouts.blinn_phong,
+                    &light_bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let pbr_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("PBR Pipeline Layout"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &material_layouts.pbr,
+                    &light_bind_group_layout,
+                    &shadow_map.bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let skybox_bind_group_layout = Skybox::create_bind_group_layout(&device);
+        let skybox_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Skybox Pipeline Layout"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &skybox_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        pipeline_cache.add_shader("vertex", vertex_shader);
+        pipeline_cache.add_shader("fragment", fragment_shader);
+        pipeline_cache.add_shader("lit_vertex", lit_vertex_shader);
+        pipeline_cache.add_shader("lit_fragment", lit_fragment_shader);
+        pipeline_cache.add_shader("tangent_vertex", tangent_vertex_shader);
+        pipeline_cache.add_shader("pbr_fragment", pbr_fragment_shader);
+        pipeline_cache.add_layout("flat", render_pipeline_layout);
+        pipeline_cache.add_layout("lit", lit_pipeline_layout);
+        pipeline_cache.add_shader("skybox_vertex", skybox_vertex_shader);
+        pipeline_cache.add_shader("skybox_fragment", skybox_fragment_shader);
+        pipeline_cache.add_layout("pbr", pbr_pipeline_layout);
+        pipeline_cache.add_layout("skybox", skybox_pipeline_layout);
+
+        let supported_sample_counts = supported_sample_counts(&adapter);
+        let indirect_draws = IndirectDraws::new(&device);
+
+        // Shadows, culling, opaque meshes, the skybox behind them and transparent meshes into
+        // an HDR target, then post-processing onto the surface.
+        let mut render_graph = RenderGraph::new(config.width, config.height, config.format);
+        render_graph.add_external(SHADOW_MAP);
+        render_graph.add_external(INDIRECT_ARGS);
+        render_graph.add_texture(&device, HDR, TextureDesc::attachment(Texture::HDR_FORMAT));
+        add_scene_targets(&mut render_graph, &device, render_settings.sample_count);
+        PostProcessStack::add_resources(&mut render_graph, &device);
+        render_graph.add_pass(ShadowPass).expect("Failed to add the shadow pass!");
+        render_graph.add_pass(CullPass).expect("Failed to add the culling pass!");
+        render_graph.add_pass(ScenePass::multisampled(HDR, HDR_MSAA)).expect("Failed to add the scene pass!");
+        render_graph.add_pass(SkyboxPass::multisampled(HDR, HDR_MSAA)).expect("Failed to add the skybox pass!");
+        render_graph.add_pass(TransparentPass::multisampled(HDR, HDR_MSAA)).expect("Failed to add the transparent pass!");
+        render_graph.add_pass(PostProcessPass::new(HDR)).expect("Failed to add the post-processing pass!");
+
+        let post_process = PostProcessStack::new(&device, &mut pipeline_cache, PostProcessStack::default_effects(config.format));
+
         let scene = Scene::new();
         let scenes = vec![scene];
         let active_scene_index = 0;
+        let mut scheduler = Scheduler::new();
+        scheduler.add_system(Stage::PostUpdate, TransformPropagation);
+        scheduler.add_system(Stage::RenderPrep, InstanceUpload);
 
         Self {
-            surface,
+            target,
             device,
             queue,
             config,
@@ -211,10 +405,24 @@ impl State {
             noise_storage_buffer,
             projection,
             camera_controller,
-            depth_texture,
-            render_pipeline,
+            pipeline_cache,
+            render_settings,
+            supported_sample_counts,
+            material_layouts,
+            default_material,
+            light_uniform,
+            light_buffer,
+            light_bind_group,
+            shadow_map,
+            skybox_bind_group_layout,
+            skybox: None,
+            visibility: Visibility::default(),
+            indirect_draws,
+            render_graph,
+            post_process,
             active_scene_index,
             scenes,
+            scheduler,
         }
     }
 
@@ -223,9 +431,15 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.render_graph.resize(&self.device, new_size.width, new_size.height);
             self.projection.resize(new_size.width, new_size.height);
-            self.surface.configure(&self.device, &self.config);
+
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen(texture) => {
+                    *texture = Texture::create_offscreen_texture(&self.device, &self.config, "offscreen_texture");
+                },
+            }
         }
     }
 
@@ -239,7 +453,13 @@ impl State {
                         ..
                     },
                 ..
-            } => self.camera_controller.process_keyboard(*key, *state),
+            } => {
+                if *state == ElementState::Pressed && self.process_settings_key(*key) {
+                    return true;
+                }
+
+                self.camera_controller.process_keyboard(*key, *state)
+            },
 
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
@@ -259,6 +479,27 @@ impl State {
 
     pub fn update(&mut self, dt: std::time::Duration) {
         self.camera_controller.update_camera(&mut self.camera, dt);
+
+        if let Some(scene) = self.scenes.get_mut(self.active_scene_index) {
+            let context = SystemContext {
+                dt,
+                device: &self.device,
+                queue: &self.queue,
+            };
+
+            // `Stage::RenderPrep' is left to `prepare_frame()'.
+            for stage in [Stage::PreUpdate, Stage::Update, Stage::PostUpdate] {
+                self.scheduler.run_stage(stage, scene, &context);
+            }
+        }
+
+        self.prepare_frame(dt);
+    }
+
+    // Brings everything the render graph reads in line with the camera and the active scene:
+    // `Stage::RenderPrep' systems (instance uploads), the camera and light uniforms, the
+    // visibility, indirect arguments, shadow cascades and post-processing uniforms.
+    fn prepare_frame(&mut self, dt: std::time::Duration) {
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
         self.queue.write_buffer(
@@ -266,115 +507,390 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+
+        if let Some(scene) = self.scenes.get_mut(self.active_scene_index) {
+            let context = SystemContext {
+                dt,
+                device: &self.device,
+                queue: &self.queue,
+            };
+
+            self.scheduler.run_stage(Stage::RenderPrep, scene, &context);
+
+            self.light_uniform.update_lights(scene);
+            self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
+
+            // After the instance uploads, so instances are culled where they'll be drawn.
+            let frustum = Frustum::from_matrix(&(self.projection.calc_matrix() * self.camera.calc_matrix()));
+            self.visibility = Visibility::compute(scene, &frustum);
+            self.indirect_draws.prepare(&self.device, &self.queue, scene, &self.visibility, &frustum, self.render_settings.draw_mode);
+        }
+
+        self.shadow_map.update(&self.queue, self.light_uniform.shadow_direction(), &self.camera, &self.projection);
+        self.post_process.update(&self.device, &self.queue);
+    }
+
+    // Only redoes what the changed settings affect: the multisampled targets or the surface
+    // configuration. Pipelines for the new settings are built (and cached) as they're
+    // needed. Fails (changing nothing) if the adapter doesn't support the sample count.
+    pub fn set_render_settings(&mut self, settings: RenderSettings) -> Result<()> {
+        if !self.supported_sample_counts.contains(&settings.sample_count) {
+            bail!("{}x MSAA isn't supported (supported: {:?})!", settings.sample_count, self.supported_sample_counts);
+        }
+
+        let previous = std::mem::replace(&mut self.render_settings, settings);
+
+        if settings.sample_count != previous.sample_count {
+            add_scene_targets(&mut self.render_graph, &self.device, settings.sample_count);
+        }
+
+        if settings.vsync != previous.vsync {
+            self.config.present_mode = settings.present_mode();
+            if let RenderTarget::Surface(surface) = &self.target {
+                surface.configure(&self.device, &self.config);
+            }
+        }
+
+        Ok(())
+    }
+
+    // F toggles wireframe, V vsync, C backface culling, M steps through the MSAA sample
+    // counts and I through the draw modes.
+    fn process_settings_key(&mut self, key: VirtualKeyCode) -> bool {
+        let mut settings = self.render_settings;
+
+        match key {
+            VirtualKeyCode::F => settings.wireframe = !settings.wireframe,
+            VirtualKeyCode::V => settings.vsync = !settings.vsync,
+            VirtualKeyCode::C => settings.backface_culling = !settings.backface_culling,
+            VirtualKeyCode::M => {
+                let index = self.supported_sample_counts.iter()
+                    .position(|count| *count == settings.sample_count)
+                    .unwrap_or(0);
+                settings.sample_count = self.supported_sample_counts[(index + 1) % self.supported_sample_counts.len()];
+            },
+            VirtualKeyCode::I => {
+                let index = DrawMode::ALL.iter().position(|mode| *mode == settings.draw_mode).unwrap_or(0);
+                settings.draw_mode = DrawMode::ALL[(index + 1) % DrawMode::ALL.len()];
+            },
+            _ => return false,
+        }
+
+        match self.set_render_settings(settings) {
+            Ok(()) => log::info!("Render settings: {:?}", self.render_settings),
+            Err(error) => log::warn!("{}", error),
+        }
+
+        true
+    }
+
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadow_map.set_settings(&self.device, settings);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
 
-        // `begin_render_pass()' borrows encoder mutably (aka `&mut self'). We can't call
-        // `encoder.finish()' until we release that mutable borrow, hence the block here.
-        {
-           let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what @location(0) in the fragment shader targets
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(
-                                wgpu::Color {
-                                    r: 0.0,
-                                    g: 0.0,
-                                    b: 0.0,
-                                    a: 1.0,
-                                }
-                            ),
-                            store: true,
-                        }
-                    })
-                ],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
+        match &self.target {
+            RenderTarget::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-            render_pass.set_pipeline(&self.render_pipeline);
-
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-
-            for component in &self.get_active_scene().components {
-                let mesh_component = match component.as_any().downcast_ref::<MeshComponent>() {
-                    Some(c) => c,
-                    None => continue,
-                };
-
-                render_pass.set_vertex_buffer(0, mesh_component.vertex_buffer.slice(..));
-
-                let parent_object_components = &self.get_active_scene().objects.get(mesh_component.parent_index)
-                    .expect("Invalid component parent index!").components;
-
-                /* for sub_component_index in 0..parent_object_components.len() {
-                        let sub_component = self.get_active_scene().components.get(sub_component_index)
-                            .expect("Invalid sub component index!");
-                        match sub_component.as_any().downcast_ref::<InstanceComponent>() {
-                            Some(instance_component) => {
-                                ;
-                            },
-                            None => {
-                                error!("A mesh component also requires an instance component. Not rendering!");
-                                continue;
-                            },
-                        }
-                } */
-
-                match &self.get_active_scene().components.get(mesh_component.instance_component_index)
-                    .expect("Invalid instance component index (pointed to by mesh)!").as_any().downcast_ref::<InstanceComponent>() {
-                        Some(instance_component) => render_pass.set_vertex_buffer(1, instance_component.instance_buffer.slice(..)),
-                        None => {
-                            warn!("Instance component pointed to by mesh component (via index {}) isn't actually an instance. Not rendering!",
-                                mesh_component.instance_component_index);
-
-                            continue;
-                        }
-                    }
-
-                render_pass.set_index_buffer(mesh_component.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-
-                for sub_component_index in 0..parent_object_components.len() {
-                        let sub_component = self.get_active_scene().components.get(sub_component_index)
-                            .expect("Invalid sub component index!");
-                        match sub_component.as_any().downcast_ref::<InstanceComponent>() {
-                            Some(instance_component) => {
-                                render_pass.draw_indexed(0..mesh_component.num_indices, 0, 0..instance_component.instances.len() as _);
-                            },
-                            None => {
-                                // Regular drawing
-                                render_pass.draw_indexed(0..mesh_component.num_indices, 0, 0..1);
-                            },
-                        }
-                }
-            }
+                self.render_graph.execute(&mut encoder, self, &view);
+                self.queue.submit(std::iter::once(encoder.finish()));
+                output.present();
+            },
+            RenderTarget::Offscreen(texture) => {
+                self.render_graph.execute(&mut encoder, self, &texture.view);
+                self.queue.submit(std::iter::once(encoder.finish()));
+            },
         }
 
+        Ok(())
+    }
+
+    // Renders the active scene as it is right now and reads it back. Only works on headless
+    // states. Doesn't need `update()' to have run first, but doesn't advance time either:
+    // the camera controller and the systems before `Stage::RenderPrep' are left alone.
+    pub fn render_to_image(&mut self) -> Result<image::RgbaImage> {
+        self.prepare_frame(std::time::Duration::ZERO);
+
+        let texture = match &self.target {
+            RenderTarget::Offscreen(texture) => texture,
+            RenderTarget::Surface(_) => bail!("Reading frames back requires a headless state!"),
+        };
+
+        let (width, height) = (self.config.width, self.config.height);
+        // Rows copied out of a texture have to be aligned, so pad them and strip the
+        // padding again when assembling the image.
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen Render Encoder"),
+        });
+
+        self.render_graph.execute(&mut encoder, self, &texture.view);
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &output_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+
+        let buffer_slice = output_buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver only goes away if we bailed out already.
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let padded = buffer_slice.get_mapped_range();
+            for row in padded.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        output_buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("Readback buffer doesn't match a {}x{} image!", width, height))
+    }
+
+    // Saves the active scene together with the current camera placement.
+    pub fn save_active_scene<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        match self.get_active_scene() {
+            Some(scene) => scene.save(path, Some(&self.camera)),
+            None => bail!("Invalid active scene index ({})!", self.active_scene_index),
+        }
+    }
+
+    // Loads a scene file and makes it the active scene, moving the camera to where it was
+    // saved. Returns the new scene's index.
+    pub fn load_scene<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<usize> {
+        let (scene, camera) = Scene::load(&self.device, &self.queue, &self.material_layouts, path)?;
+
+        if let Some(camera) = camera {
+            self.camera = camera;
+        }
+
+        self.scenes.push(scene);
+        self.active_scene_index = self.scenes.len() - 1;
+
+        Ok(self.active_scene_index)
+    }
+
+    // Uses a latitude/longitude panorama (e.g. a Radiance .hdr) as the skybox.
+    pub fn load_skybox<P: AsRef<std::path::Path>>(&mut self, path: P, face_size: u32) -> Result<()> {
+        let path = path.as_ref();
+        let image = image::open(path)?;
+        let cubemap = Texture::cubemap_from_equirectangular(&self.device, &self.queue, &image, face_size, &path.display().to_string())?;
+        self.set_skybox_cubemap(Some(Rc::new(cubemap)));
 
         Ok(())
     }
 
-    fn get_active_scene(&self) -> &Scene {
+    // Uses six images (+X, -X, +Y, -Y, +Z, -Z) as the skybox' faces.
+    pub fn load_skybox_faces<P: AsRef<std::path::Path>>(&mut self, paths: &[P; 6]) -> Result<()> {
+        let faces = [
+            image::open(&paths[0])?, image::open(&paths[1])?, image::open(&paths[2])?,
+            image::open(&paths[3])?, image::open(&paths[4])?, image::open(&paths[5])?,
+        ];
+        let label = paths[0].as_ref().display().to_string();
+        let cubemap = Texture::cubemap_from_images(&self.device, &self.queue, &faces, &label)?;
+        self.set_skybox_cubemap(Some(Rc::new(cubemap)));
+
+        Ok(())
+    }
+
+    // `None' removes the skybox.
+    pub fn set_skybox_cubemap(&mut self, cubemap: Option<Rc<Texture>>) {
+        self.skybox = cubemap.map(|cubemap| Skybox::new(&self.device, &self.skybox_bind_group_layout, cubemap));
+    }
+
+    pub fn skybox_pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            vertex_shader: "skybox_vertex",
+            fragment_shader: Some("skybox_fragment"),
+            layout: "skybox",
+            vertex_buffers: vec![],
+            color_targets: vec![ColorTargetKey {
+                format: Texture::HDR_FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+            }],
+            // Only passes where the depth buffer is still clear.
+            depth: Some(DepthKey {
+                format: Texture::DEPTH_FORMAT,
+                write_enabled: false,
+                compare: wgpu::CompareFunction::LessEqual,
+                bias: DepthBiasKey::default(),
+            }),
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            sample_count: self.render_settings.sample_count,
+        }
+    }
+
+    // Describes the pipeline meshes of `layout' with `shading' materials are drawn with, under
+    // the current render settings.
+    pub fn scene_pipeline_key(&self, layout: VertexLayout, shading: ShadingModel, alpha_mode: AlphaMode) -> PipelineKey {
+        // Vertex colours are drawn flat whatever the shading model, anything with
+        // normals is lit and textured through its material.
+        let vertex_shader = match layout {
+            VertexLayout::Pure => "vertex",
+            VertexLayout::Model => "lit_vertex",
+            VertexLayout::Tangent => "tangent_vertex",
+        };
+        let (pipeline_layout, fragment_shader) = match (layout, shading) {
+            (VertexLayout::Pure, _) => ("flat", "fragment"),
+            (_, ShadingModel::BlinnPhong) => ("lit", "lit_fragment"),
+            (_, ShadingModel::Pbr) => ("pbr", "pbr_fragment"),
+        };
+
+        PipelineKey {
+            vertex_shader,
+            fragment_shader: Some(fragment_shader),
+            layout: pipeline_layout,
+            vertex_buffers: vec![(&layout.desc()).into(), (&InstanceRaw::desc()).into()],
+            color_targets: vec![ColorTargetKey {
+                format: Texture::HDR_FORMAT,
+                blend: match alpha_mode {
+                    AlphaMode::Opaque => Some(wgpu::BlendState::REPLACE),
+                    AlphaMode::Blend => Some(wgpu::BlendState::ALPHA_BLENDING),
+                },
+            }],
+            depth: Some(DepthKey {
+                format: Texture::DEPTH_FORMAT,
+                write_enabled: alpha_mode == AlphaMode::Opaque,
+                compare: wgpu::CompareFunction::Less,
+                bias: DepthBiasKey::default(),
+            }),
+            cull_mode: self.render_settings.cull_mode(),
+            polygon_mode: self.render_settings.polygon_mode(),
+            sample_count: self.render_settings.sample_count,
+        }
+    }
+
+    pub fn get_active_scene(&self) -> Option<&Scene> {
         self.scenes.get(self.active_scene_index)
-            .expect(format!("Invalid active scene index ({})!", self.active_scene_index).as_str())
+    }
+
+    pub fn get_active_scene_mut(&mut self) -> Option<&mut Scene> {
+        self.scenes.get_mut(self.active_scene_index)
     }
 }
 
+// (Re)declares the scene pass' depth buffer and multisampled colour target. Without MSAA
+// the colour target is unused, so it's kept at 1x1.
+fn add_scene_targets(graph: &mut RenderGraph, device: &wgpu::Device, sample_count: u32) {
+    graph.add_texture(device, DEPTH, TextureDesc {
+        sample_count,
+        ..TextureDesc::attachment(Texture::DEPTH_FORMAT)
+    });
+
+    graph.add_texture(device, HDR_MSAA, TextureDesc {
+        size: if sample_count > 1 { TextureSize::Surface { scale: 1.0 } } else { TextureSize::Fixed { width: 1, height: 1 } },
+        format: Texture::HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        sample_count,
+    });
+}
+
+// Only 1 and 4 samples are offered. wgpu 0.13 can't tell which other counts a format
+// supports (its format features have a single MULTISAMPLE flag, which only promises 4)
+// and doesn't validate the count itself, so anything else could fail in the driver.
+fn supported_sample_counts(adapter: &wgpu::Adapter) -> Vec<u32> {
+    let multisample = [Texture::HDR_FORMAT, Texture::DEPTH_FORMAT].iter().all(|format| {
+        adapter.get_texture_format_features(*format).flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE)
+    });
+
+    if multisample {
+        vec![1, 4]
+    } else {
+        vec![1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::component::instance::{Instance, InstanceComponent};
+    use crate::primitives;
+
+    // The middle of the image, where the cube is, has to differ from its corner, where only
+    // the clear colour is.
+    fn assert_cube_drawn(image: &image::RgbaImage, draw_mode: DrawMode) {
+        let (width, height) = image.dimensions();
+        let (center, corner) = (image.get_pixel(width / 2, height / 2), image.get_pixel(0, 0));
+
+        assert_ne!(center, corner, "Nothing was drawn with {:?}", draw_mode);
+    }
+
+    // Needs an adapter, which most CI runners lack. Run with `cargo test -- --ignored'.
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn instances_beyond_capacity_still_render() {
+        let mut state = pollster::block_on(State::new_headless(64, 64));
+        state.camera = Camera::new((0.0, 0.0, 3.0), cgmath::Deg(-90.0), cgmath::Deg(0.0));
+
+        let mesh = primitives::cube(&state.device, 1.0);
+        let instances = InstanceComponent::from_instances(&state.device, vec![Instance::default()]);
+        let scene = state.get_active_scene_mut().unwrap();
+        // Lit evenly, so the cube shows without any lights.
+        scene.ambient_light = [1.0; 3];
+        let entity = scene.spawn();
+        scene.add_component(entity, mesh);
+        scene.add_component(entity, instances);
+
+        for draw_mode in [DrawMode::Direct, DrawMode::Indirect, DrawMode::GpuCulled] {
+            state.set_render_settings(RenderSettings { draw_mode, ..state.render_settings }).unwrap();
+            assert_cube_drawn(&state.render_to_image().unwrap(), draw_mode);
+
+            // Past the capacity, so the next upload has to grow the buffer. The new
+            // instances line up to the right of the first one.
+            let scene = state.get_active_scene_mut().unwrap();
+            let instances = scene.component_mut::<InstanceComponent>(entity).unwrap();
+            let capacity = instances.capacity();
+            while instances.len() <= capacity {
+                let x = instances.len() as f32 * 1.5;
+                instances.push(Instance { position: cgmath::Vector3::new(x, 0.0, 0.0), ..Instance::default() });
+            }
+
+            assert_cube_drawn(&state.render_to_image().unwrap(), draw_mode);
+
+            let instances = state.get_active_scene().unwrap().component::<InstanceComponent>(entity).unwrap();
+            assert!(instances.capacity() > capacity);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use image::GenericImageView;
 
 pub struct Texture {
@@ -9,6 +9,13 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    // What the scene is rendered into before post-processing.
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    // For images holding colours (base colour, emissive, ...).
+    pub const SRGB_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    // For images holding data rather than colours (normal maps, metallic/roughness, ...).
+    pub const LINEAR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 
     pub fn from_bytes(
         device: &wgpu::Device,
@@ -21,11 +28,45 @@ impl Texture {
         Self::from_image(device, queue, &img, Some(label))
     }
 
+    // A 1x1 texture of a single colour, e.g. to stand in for a missing image.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str
+    ) -> Self {
+        Self::from_color_with_format(device, queue, color, label, Self::SRGB_FORMAT)
+    }
+
+    pub fn from_color_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+
+        // A 1x1 RGBA image always converts.
+        Self::from_image_with_format(device, queue, &img, Some(label), format).unwrap()
+    }
+
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>
+    ) -> Result<Self> {
+        Self::from_image_with_format(device, queue, img, label, Self::SRGB_FORMAT)
+    }
+
+    // `format' has to be one of the 8-bit RGBA formats.
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -42,7 +83,7 @@ impl Texture {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             }
         );
@@ -115,5 +156,308 @@ impl Texture {
 
         Self { texture, view, sampler }
     }
+
+    // Intermediate attachment of a render graph (see src/render_graph). Sampled linearly,
+    // so post-processing passes can read it.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
+
+    // An empty cube map, `size' texels square per face. Faces are the array layers in
+    // +X, -X, +Y, -Y, +Z, -Z order.
+    pub fn create_cubemap(device: &wgpu::Device, size: u32, format: wgpu::TextureFormat, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
+
+    // Six square faces of the same size, in +X, -X, +Y, -Y, +Z, -Z order. Stored as
+    // `HDR_FORMAT', so HDR images keep their range, 8 bit images are taken to be sRGB.
+    pub fn cubemap_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: &str,
+    ) -> Result<Self> {
+        let size = faces[0].width();
+        if size == 0 {
+            bail!("Can't build cube map {} from empty faces!", label);
+        }
+        if faces.iter().any(|face| face.dimensions() != (size, size)) {
+            bail!("Cube map faces of {} have to be square and all the same size!", label);
+        }
+
+        let cubemap = Self::create_cubemap(device, size, Self::HDR_FORMAT, label);
+        for (layer, face) in faces.iter().enumerate() {
+            let texels = to_linear_rgba32f(face).into_raw();
+            cubemap.write_cubemap_face(queue, layer as u32, size, &texels);
+        }
+
+        Ok(cubemap)
+    }
+
+    // Reprojects a latitude/longitude panorama (e.g. a Radiance .hdr) onto a cube map with
+    // `face_size' texels per side, sampling it bilinearly.
+    pub fn cubemap_from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::DynamicImage,
+        face_size: u32,
+        label: &str,
+    ) -> Result<Self> {
+        if image.width() == 0 || image.height() == 0 || face_size == 0 {
+            bail!("Can't build cube map {} from an empty image!", label);
+        }
+
+        let panorama = to_linear_rgba32f(image);
+        let cubemap = Self::create_cubemap(device, face_size, Self::HDR_FORMAT, label);
+
+        for layer in 0..6 {
+            let mut texels = Vec::with_capacity((face_size * face_size * 4) as usize);
+            for y in 0..face_size {
+                for x in 0..face_size {
+                    let s = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                    let t = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                    texels.extend(sample_equirectangular(&panorama, cube_face_direction(layer, s, t)));
+                }
+            }
+
+            cubemap.write_cubemap_face(queue, layer, face_size, &texels);
+        }
+
+        Ok(cubemap)
+    }
+
+    // `texels' is RGBA, converted to half floats on the way. Texels too bright for a half
+    // float (the sun in a panorama, say) are clamped rather than turned into infinity.
+    fn write_cubemap_face(&self, queue: &wgpu::Queue, layer: u32, size: u32, texels: &[f32]) {
+        let max = half::f16::MAX.to_f32();
+        let halves = texels.iter().map(|texel| half::f16::from_f32(texel.min(max)).to_bits()).collect::<Vec<u16>>();
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+            },
+            bytemuck::cast_slice(&halves),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(8 * size),
+                rows_per_image: std::num::NonZeroU32::new(size),
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    // One square depth layer per shadow cascade. `view' covers every layer (for sampling),
+    // render passes need a view of a single layer. Sampled through a comparison sampler.
+    pub fn create_shadow_map_texture(device: &wgpu::Device, resolution: u32, layers: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                compare: Some(wgpu::CompareFunction::LessEqual),
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
+
+    // Stands in for the swapchain when rendering without a window. It can be copied
+    // out of, which is how headless frames get read back.
+    pub fn create_offscreen_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::TEXTURE_BINDING,
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
 }
 
+// World direction through texel (`s', `t') (both -1..1, `t' pointing down) of cube face
+// `layer', following the usual cube map face layout.
+fn cube_face_direction(layer: u32, s: f32, t: f32) -> [f32; 3] {
+    match layer {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    }
+}
+
+// Float images are already linear, anything else is decoded from sRGB.
+fn to_linear_rgba32f(image: &image::DynamicImage) -> image::Rgba32FImage {
+    let mut rgba = image.to_rgba32f();
+
+    if !matches!(image, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)) {
+        for pixel in rgba.pixels_mut() {
+            for channel in pixel.0.iter_mut().take(3) {
+                *channel = if *channel <= 0.04045 {
+                    *channel / 12.92
+                } else {
+                    ((*channel + 0.055) / 1.055).powf(2.4)
+                };
+            }
+        }
+    }
+
+    rgba
+}
+
+fn sample_equirectangular(panorama: &image::Rgba32FImage, direction: [f32; 3]) -> [f32; 4] {
+    let [x, y, z] = direction;
+    let length = (x * x + y * y + z * z).sqrt();
+    let u = 0.5 + z.atan2(x) / (2.0 * std::f32::consts::PI);
+    let v = 0.5 - (y / length).clamp(-1.0, 1.0).asin() / std::f32::consts::PI;
+
+    let (width, height) = panorama.dimensions();
+    let px = u * width as f32 - 0.5;
+    let py = (v * height as f32 - 0.5).clamp(0.0, height as f32 - 1.0);
+    let (x0, y0) = (px.floor(), py.floor());
+    let (fx, fy) = (px - x0, py - y0);
+
+    // Wraps around horizontally, clamps at the poles.
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as u32).min(height - 1);
+        panorama.get_pixel(x, y).0
+    };
+
+    let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+    let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+
+    let mut result = [0.0; 4];
+    for i in 0..4 {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        result[i] = top + (bottom - top) * fy;
+    }
+
+    result
+}
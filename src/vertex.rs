@@ -1,16 +1,63 @@
 use std::mem;
+use serde::{Deserialize, Serialize};
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
 }
 
+// Identifies which `Vertex' type a mesh's buffer holds, so a matching pipeline can be
+// picked for it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VertexLayout {
+    Pure,
+    Model,
+    Tangent,
+}
+
+impl VertexLayout {
+    pub const ALL: [VertexLayout; 3] = [VertexLayout::Pure, VertexLayout::Model, VertexLayout::Tangent];
+
+    pub fn desc<'a>(&self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            VertexLayout::Pure => PureVertex::desc(),
+            VertexLayout::Model => ModelVertex::desc(),
+            VertexLayout::Tangent => TangentVertex::desc(),
+        }
+    }
+
+    pub fn has_normals(&self) -> bool {
+        *self != VertexLayout::Pure
+    }
+
+    pub fn has_tex_coords(&self) -> bool {
+        *self != VertexLayout::Pure
+    }
+}
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize)]
 pub struct PureVertex {
     pub position: [f32; 3],
     pub color: [f32; 3],
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize)]
+pub struct TangentVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+    // xyz is the tangent, w the handedness of the bitangent (as in glTF).
+    pub tangent: [f32; 4],
+}
 
 impl Vertex for PureVertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
@@ -27,16 +74,135 @@ impl Vertex for PureVertex {
                 wgpu::VertexAttribute {
                     offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+impl Vertex for ModelVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
                 },
 
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
                     shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+impl Vertex for TangentVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
 
+// Vertex data of a mesh, tagged with its layout.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MeshVertices {
+    Pure(Vec<PureVertex>),
+    Model(Vec<ModelVertex>),
+    Tangent(Vec<TangentVertex>),
+}
+
+impl MeshVertices {
+    pub fn layout(&self) -> VertexLayout {
+        match self {
+            MeshVertices::Pure(_) => VertexLayout::Pure,
+            MeshVertices::Model(_) => VertexLayout::Model,
+            MeshVertices::Tangent(_) => VertexLayout::Tangent,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            MeshVertices::Pure(v) => v.len(),
+            MeshVertices::Model(v) => v.len(),
+            MeshVertices::Tangent(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            MeshVertices::Pure(v) => bytemuck::cast_slice(v),
+            MeshVertices::Model(v) => bytemuck::cast_slice(v),
+            MeshVertices::Tangent(v) => bytemuck::cast_slice(v),
+        }
+    }
+
+    pub fn positions(&self) -> Vec<[f32; 3]> {
+        match self {
+            MeshVertices::Pure(v) => v.iter().map(|v| v.position).collect(),
+            MeshVertices::Model(v) => v.iter().map(|v| v.position).collect(),
+            MeshVertices::Tangent(v) => v.iter().map(|v| v.position).collect(),
+        }
+    }
+}
+
+impl From<Vec<PureVertex>> for MeshVertices {
+    fn from(vertices: Vec<PureVertex>) -> Self {
+        MeshVertices::Pure(vertices)
+    }
+}
+
+impl From<Vec<ModelVertex>> for MeshVertices {
+    fn from(vertices: Vec<ModelVertex>) -> Self {
+        MeshVertices::Model(vertices)
+    }
+}
+
+impl From<Vec<TangentVertex>> for MeshVertices {
+    fn from(vertices: Vec<TangentVertex>) -> Self {
+        MeshVertices::Tangent(vertices)
+    }
+}
@@ -4,7 +4,7 @@ use winit::{
 };
 
 pub fn create_window(event_loop: &EventLoop<()>) -> Window         {
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let window = WindowBuilder::new().build(event_loop).unwrap();
 
     window.set_title("Sit");
     // window.set_decorations(false);